@@ -0,0 +1,212 @@
+use crate::errors::VulkanRError;
+
+/// Regular height grid, row-major with the first row first, plus the
+/// horizontal cell spacing in the same units as the heights.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid<'a> {
+    pub z: &'a [f32],
+    pub rows: usize,
+    pub cols: usize,
+    /// Spacing between columns.
+    pub dx: f32,
+    /// Spacing between rows.
+    pub dy: f32,
+}
+
+impl<'a> Grid<'a> {
+    pub fn new(z: &'a [f32], rows: usize, cols: usize, dx: f32, dy: f32) -> Result<Self, VulkanRError> {
+        if z.len() != rows * cols {
+            return Err(VulkanRError::InvalidInput {
+                param: "z",
+                reason: format!("z length {} doesn't match rows*cols {}", z.len(), rows * cols),
+            });
+        }
+        if rows < 2 || cols < 2 {
+            return Err(VulkanRError::InvalidInput {
+                param: "z",
+                reason: format!("grid must be at least 2x2, got {rows}x{cols}"),
+            });
+        }
+        if !(dx > 0.0 && dx.is_finite() && dy > 0.0 && dy.is_finite()) {
+            return Err(VulkanRError::InvalidInput {
+                param: "cell_size",
+                reason: format!("cell sizes must be positive, got ({dx}, {dy})"),
+            });
+        }
+        Ok(Self { z, rows, cols, dx, dy })
+    }
+
+    #[inline]
+    pub fn at(&self, i: usize, j: usize) -> f32 {
+        self.z[i * self.cols + j]
+    }
+}
+
+/// Height derivatives `(dz/dx, dz/dy)` at a cell, per unit distance along
+/// columns and rows. Central differences inside the grid, one-sided on the
+/// edges and next to missing (non-finite) cells, 0 where both neighbours are
+/// missing and NaN on missing cells. This is what the renderer uses for
+/// vertex normals.
+pub fn gradient(grid: &Grid, i: usize, j: usize) -> (f32, f32) {
+    let z = grid.at(i, j);
+    if !z.is_finite() {
+        return (f32::NAN, f32::NAN);
+    }
+    let west = j.checked_sub(1).map(|j| grid.at(i, j));
+    let east = (j + 1 < grid.cols).then(|| grid.at(i, j + 1));
+    let north = i.checked_sub(1).map(|i| grid.at(i, j));
+    let south = (i + 1 < grid.rows).then(|| grid.at(i + 1, j));
+    (derivative(west, z, east, grid.dx), derivative(north, z, south, grid.dy))
+}
+
+fn derivative(before: Option<f32>, center: f32, after: Option<f32>, spacing: f32) -> f32 {
+    match (before.filter(|v| v.is_finite()), after.filter(|v| v.is_finite())) {
+        (Some(b), Some(a)) => (a - b) / (2.0 * spacing),
+        (Some(b), None) => (center - b) / spacing,
+        (None, Some(a)) => (a - center) / spacing,
+        (None, None) => 0.0,
+    }
+}
+
+/// Slope of every cell in degrees from horizontal. Missing cells are NaN.
+pub fn slope(grid: &Grid) -> Vec<f32> {
+    map_cells(grid.rows, grid.cols, |i, j| {
+        let (gx, gy) = gradient(grid, i, j);
+        gx.hypot(gy).atan().to_degrees()
+    })
+}
+
+/// Downslope direction of every cell in degrees clockwise from north, where
+/// north is the first row and east the last column. Flat and missing cells
+/// are NaN.
+pub fn aspect(grid: &Grid) -> Vec<f32> {
+    map_cells(grid.rows, grid.cols, |i, j| {
+        let (gx, gy) = gradient(grid, i, j);
+        aspect_deg(gx, gy)
+    })
+}
+
+fn aspect_deg(gx: f32, gy: f32) -> f32 {
+    if gx == 0.0 && gy == 0.0 {
+        return f32::NAN;
+    }
+    // Downslope vector in (east, north) is (-gx, gy) as rows run southwards
+    (-gx).atan2(gy).to_degrees().rem_euclid(360.0)
+}
+
+/// Hillshade in [0, 1] for a light at `altitude_deg` above the horizon,
+/// averaged over the given azimuths (degrees clockwise from north). Several
+/// azimuths give a multidirectional hillshade. Missing cells are NaN.
+pub fn hillshade(grid: &Grid, azimuths_deg: &[f32], altitude_deg: f32) -> Result<Vec<f32>, VulkanRError> {
+    if azimuths_deg.is_empty() {
+        return Err(VulkanRError::InvalidInput {
+            param: "azimuth",
+            reason: "needs at least one azimuth".into(),
+        });
+    }
+    if !(0.0..=90.0).contains(&altitude_deg) {
+        return Err(VulkanRError::InvalidInput {
+            param: "altitude",
+            reason: format!("must be in [0, 90], got {altitude_deg}"),
+        });
+    }
+    let zenith = (90.0 - altitude_deg).to_radians();
+    let azimuths: Vec<f32> = azimuths_deg.iter().map(|a| a.to_radians()).collect();
+    Ok(map_cells(grid.rows, grid.cols, |i, j| {
+        let (gx, gy) = gradient(grid, i, j);
+        let slope = gx.hypot(gy).atan();
+        if slope.is_nan() {
+            return f32::NAN;
+        }
+        let aspect = aspect_deg(gx, gy).to_radians();
+        let shade: f32 = azimuths
+            .iter()
+            .map(|&az| {
+                let directional = if aspect.is_nan() { 0.0 } else { slope.sin() * (az - aspect).cos() };
+                (zenith.cos() * slope.cos() + zenith.sin() * directional).max(0.0)
+            })
+            .sum();
+        shade / azimuths.len() as f32
+    }))
+}
+
+/// Evaluate `f(i, j)` for every cell, splitting rows across threads.
+pub(crate) fn map_cells<T, F>(rows: usize, cols: usize, f: F) -> Vec<T>
+where
+    T: Clone + Default + Send,
+    F: Fn(usize, usize) -> T + Sync,
+{
+    let mut out = vec![T::default(); rows * cols];
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(rows);
+    let rows_per_chunk = rows.div_ceil(threads);
+    std::thread::scope(|scope| {
+        for (chunk_idx, chunk) in out.chunks_mut(rows_per_chunk * cols).enumerate() {
+            let f = &f;
+            scope.spawn(move || {
+                for (k, v) in chunk.iter_mut().enumerate() {
+                    let (i, j) = (chunk_idx * rows_per_chunk + k / cols, k % cols);
+                    *v = f(i, j);
+                }
+            });
+        }
+    });
+    out
+}
+
+/// Sky-view factor per cell: the fraction of the sky hemisphere visible
+/// from the cell, 1 on open flat ground and lower in valleys and gullies.
+///
+/// The horizon is traced along `directions` evenly spaced azimuths up to
+/// `radius` cells away; each direction contributes `1 - sin(horizon angle)`.
+/// Non-finite cells yield NaN and are skipped as occluders.
+pub fn sky_view_factor(grid: &Grid, directions: usize, radius: usize) -> Result<Vec<f32>, VulkanRError> {
+    if directions == 0 {
+        return Err(VulkanRError::InvalidInput {
+            param: "directions",
+            reason: "must be at least 1".into(),
+        });
+    }
+    if radius == 0 {
+        return Err(VulkanRError::InvalidInput {
+            param: "radius",
+            reason: "must be at least 1 cell".into(),
+        });
+    }
+    let dirs: Vec<(f32, f32)> = (0..directions)
+        .map(|d| {
+            let theta = std::f32::consts::TAU * d as f32 / directions as f32;
+            (theta.sin(), theta.cos())
+        })
+        .collect();
+
+    Ok(map_cells(grid.rows, grid.cols, |i, j| {
+        let z0 = grid.at(i, j);
+        if !z0.is_finite() {
+            return f32::NAN;
+        }
+        let mut open = 0.0;
+        for &(si, sj) in &dirs {
+            // Steepest upward slope towards the horizon in this direction
+            let mut max_slope = 0.0f32;
+            for k in 1..=radius {
+                let r = k as f32;
+                let (pi, pj) = (i as f32 + r * si, j as f32 + r * sj);
+                let (pi, pj) = (pi.round(), pj.round());
+                if pi < 0.0 || pj < 0.0 || pi >= grid.rows as f32 || pj >= grid.cols as f32 {
+                    break;
+                }
+                let h = grid.at(pi as usize, pj as usize);
+                if !h.is_finite() {
+                    continue;
+                }
+                let dist = ((pj - j as f32) * grid.dx).hypot((pi - i as f32) * grid.dy);
+                if dist > 0.0 {
+                    max_slope = max_slope.max((h - z0) / dist);
+                }
+            }
+            let sin_horizon = max_slope / (1.0 + max_slope * max_slope).sqrt();
+            open += 1.0 - sin_horizon;
+        }
+        open / directions as f32
+    }))
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use crate::errors::VulkanRError;
+
+/// Value of a named argument from R. Numeric and integer vectors are both
+/// `Num`; NULL is an empty `Num`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgValue {
+    Num(Vec<f64>),
+    Str(String),
+    Flag(bool),
+    Raw(Vec<u8>),
+}
+
+/// Named arguments, such as the render options list built in R, read by
+/// name and type so that they can't be mixed up by position.
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    values: HashMap<String, ArgValue>,
+}
+
+impl Args {
+    /// Collect `values`, rejecting names outside `known` and repeated ones.
+    pub fn new(known: &[&str], values: impl IntoIterator<Item = (String, ArgValue)>) -> Result<Self, VulkanRError> {
+        let mut args = Self::default();
+        for (name, value) in values {
+            if !known.contains(&name.as_str()) {
+                return Err(VulkanRError::InvalidInput {
+                    param: "options",
+                    reason: format!("unknown option '{name}'"),
+                });
+            }
+            if args.values.insert(name.clone(), value).is_some() {
+                return Err(VulkanRError::InvalidInput {
+                    param: "options",
+                    reason: format!("option '{name}' is given twice"),
+                });
+            }
+        }
+        Ok(args)
+    }
+
+    fn get(&self, name: &'static str) -> Result<&ArgValue, VulkanRError> {
+        self.values.get(name).ok_or_else(|| VulkanRError::InvalidInput {
+            param: name,
+            reason: "is missing".into(),
+        })
+    }
+
+    /// Numeric vector of any length.
+    pub fn nums(&self, name: &'static str) -> Result<&[f64], VulkanRError> {
+        match self.get(name)? {
+            ArgValue::Num(v) => Ok(v),
+            other => Err(type_error(name, "numeric", other)),
+        }
+    }
+
+    /// Single number.
+    pub fn num(&self, name: &'static str) -> Result<f64, VulkanRError> {
+        match *self.nums(name)? {
+            [v] => Ok(v),
+            ref v => Err(VulkanRError::InvalidInput {
+                param: name,
+                reason: format!("must be a single number, got length {}", v.len()),
+            }),
+        }
+    }
+
+    /// Single whole number.
+    pub fn int(&self, name: &'static str) -> Result<i32, VulkanRError> {
+        let v = self.num(name)?;
+        if v.fract() != 0.0 || v.abs() > i32::MAX as f64 {
+            return Err(VulkanRError::InvalidInput {
+                param: name,
+                reason: format!("must be a whole number, got {v}"),
+            });
+        }
+        Ok(v as i32)
+    }
+
+    /// Single string.
+    pub fn string(&self, name: &'static str) -> Result<&str, VulkanRError> {
+        match self.get(name)? {
+            ArgValue::Str(s) => Ok(s),
+            other => Err(type_error(name, "a single string", other)),
+        }
+    }
+
+    /// Single logical.
+    pub fn flag(&self, name: &'static str) -> Result<bool, VulkanRError> {
+        match self.get(name)? {
+            ArgValue::Flag(b) => Ok(*b),
+            other => Err(type_error(name, "TRUE or FALSE", other)),
+        }
+    }
+
+    /// Raw bytes.
+    pub fn raw(&self, name: &'static str) -> Result<&[u8], VulkanRError> {
+        match self.get(name)? {
+            ArgValue::Raw(v) => Ok(v),
+            other => Err(type_error(name, "a raw vector", other)),
+        }
+    }
+}
+
+fn type_error(name: &'static str, expected: &str, got: &ArgValue) -> VulkanRError {
+    let got = match got {
+        ArgValue::Num(_) => "numeric",
+        ArgValue::Str(_) => "a string",
+        ArgValue::Flag(_) => "logical",
+        ArgValue::Raw(_) => "raw",
+    };
+    VulkanRError::InvalidInput {
+        param: name,
+        reason: format!("must be {expected}, got {got}"),
+    }
+}
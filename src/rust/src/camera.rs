@@ -0,0 +1,232 @@
+use glam::{Mat4, Vec3};
+
+use crate::errors::VulkanRError;
+
+/// Projection used to map view space to clip space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Perspective projection with a vertical field of view in degrees.
+    Perspective { fov_deg: f32 },
+    /// Orthographic projection. `height` is the visible extent in world
+    /// units; `None` fits the scene bounds.
+    Orthographic { height: Option<f32> },
+}
+
+/// Where the camera sits and what it looks at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraView {
+    /// Orbit around `target` (scene center when `None`). Azimuth is measured
+    /// around the vertical axis from +z towards +x, elevation above the
+    /// horizontal plane. `distance` of `None` fits the scene bounds.
+    Orbit {
+        azimuth_deg: f32,
+        elevation_deg: f32,
+        distance: Option<f32>,
+        target: Option<Vec3>,
+    },
+    /// Raw look-at form in scene coordinates.
+    LookAt { eye: Vec3, target: Vec3, up: Vec3 },
+}
+
+/// Camera description resolved against the scene bounds at render time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub view: CameraView,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    /// Oblique view along the (1, 1, 1) diagonal with a 35° field of view.
+    fn default() -> Self {
+        Self {
+            view: CameraView::Orbit {
+                azimuth_deg: 45.0,
+                elevation_deg: 35.264_39,
+                distance: None,
+                target: None,
+            },
+            projection: Projection::Perspective { fov_deg: 35.0 },
+        }
+    }
+}
+
+/// Camera matrices resolved for a particular scene.
+#[derive(Debug, Clone, Copy)]
+pub struct ResolvedCamera {
+    pub eye: Vec3,
+    pub view: Mat4,
+    pub proj: Mat4,
+    pub near: f32,
+    pub far: f32,
+    pub orthographic: bool,
+}
+
+impl ResolvedCamera {
+    pub fn view_proj(&self) -> Mat4 {
+        self.proj * self.view
+    }
+
+    /// Convert a [0, 1] depth-buffer value to the distance from the eye
+    /// along the view axis, in scene units.
+    pub fn linear_depth(&self, d: f32) -> f32 {
+        if self.orthographic {
+            self.near + d * (self.far - self.near)
+        } else {
+            self.near * self.far / (self.far - d * (self.far - self.near))
+        }
+    }
+}
+
+impl Camera {
+    pub fn orbit(azimuth_deg: f32, elevation_deg: f32, distance: Option<f32>, target: Option<Vec3>) -> Self {
+        Self {
+            view: CameraView::Orbit { azimuth_deg, elevation_deg, distance, target },
+            ..Self::default()
+        }
+    }
+
+    /// Look-at camera with the usual up vector for terrain views: +y, or
+    /// for views straight up or down the up vector of [`Camera::orbit`] at
+    /// azimuth 0: -z looking down and +z looking up.
+    pub fn look_at_terrain(eye: Vec3, target: Vec3) -> Self {
+        let dir = (target - eye).normalize_or_zero();
+        let up = if dir.cross(Vec3::Y).length_squared() < 1e-8 { Vec3::Z * dir.y.signum() } else { Vec3::Y };
+        Self::look_at(eye, target, up)
+    }
+
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        Self {
+            view: CameraView::LookAt { eye, target, up },
+            ..Self::default()
+        }
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Check parameters that can be rejected without knowing the scene.
+    pub fn validate(&self) -> Result<(), VulkanRError> {
+        match self.projection {
+            Projection::Perspective { fov_deg } if !(fov_deg > 0.0 && fov_deg < 180.0) => {
+                return Err(VulkanRError::InvalidInput {
+                    param: "fov_deg",
+                    reason: format!("must be in (0, 180), got {fov_deg}"),
+                });
+            }
+            Projection::Orthographic { height: Some(h) } if !(h > 0.0 && h.is_finite()) => {
+                return Err(VulkanRError::InvalidInput {
+                    param: "ortho_height",
+                    reason: format!("must be positive, got {h}"),
+                });
+            }
+            _ => {}
+        }
+        match self.view {
+            CameraView::Orbit { elevation_deg, distance, .. } => {
+                if !(-90.0..=90.0).contains(&elevation_deg) {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "elevation",
+                        reason: format!("must be in [-90, 90], got {elevation_deg}"),
+                    });
+                }
+                if let Some(d) = distance {
+                    if !(d > 0.0 && d.is_finite()) {
+                        return Err(VulkanRError::InvalidInput {
+                            param: "distance",
+                            reason: format!("must be positive, got {d}"),
+                        });
+                    }
+                }
+            }
+            CameraView::LookAt { eye, target, up } => {
+                let dir = target - eye;
+                if dir.length_squared() < 1e-12 {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "eye",
+                        reason: "eye and target must differ".into(),
+                    });
+                }
+                if dir.cross(up).length_squared() < 1e-12 {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "up",
+                        reason: "up must not be parallel to the view direction".into(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve view and projection matrices for a scene with the given
+    /// axis-aligned bounds. Near/far planes always enclose the bounding sphere.
+    pub fn resolve(&self, bounds: (Vec3, Vec3), aspect: f32) -> Result<ResolvedCamera, VulkanRError> {
+        self.validate()?;
+
+        let (bmin, bmax) = bounds;
+        let center = (bmin + bmax) * 0.5;
+        let radius = ((bmax - bmin).length() * 0.5).max(1e-3);
+
+        let (eye, target, up) = match self.view {
+            CameraView::Orbit { azimuth_deg, elevation_deg, distance, target } => {
+                let target = target.unwrap_or(center);
+                let (az, el) = (azimuth_deg.to_radians(), elevation_deg.to_radians());
+                let dir = Vec3::new(el.cos() * az.sin(), el.sin(), el.cos() * az.cos());
+                // Radius of the sphere around `target` that still contains the scene
+                let reach = radius + (center - target).length();
+                let distance = distance.unwrap_or_else(|| match self.projection {
+                    Projection::Perspective { fov_deg } => {
+                        fit_sphere(reach, fov_deg.to_radians(), aspect)
+                    }
+                    Projection::Orthographic { .. } => 2.0 * reach,
+                });
+                // Straight up/down views turn with the azimuth like the views
+                // just short of them: looking down, the side of the terrain
+                // away from the eye's azimuth is at the top (-z at azimuth 0)
+                let up = if el.cos() < 1e-4 {
+                    Vec3::new(-az.sin(), 0.0, -az.cos()) * el.sin().signum()
+                } else {
+                    Vec3::Y
+                };
+                (target + dir * distance, target, up)
+            }
+            CameraView::LookAt { eye, target, up } => (eye, target, up),
+        };
+
+        let view = Mat4::look_at_rh(eye, target, up);
+
+        // Depth range along the view axis covering the whole bounding sphere
+        let forward = (target - eye).normalize();
+        let center_depth = (center - eye).dot(forward);
+        let far = (center_depth + radius).max(1e-3);
+
+        let (proj, near) = match self.projection {
+            Projection::Perspective { fov_deg } => {
+                let near = (center_depth - radius).max(far * 1e-4);
+                (Mat4::perspective_rh(fov_deg.to_radians(), aspect, near, far), near)
+            }
+            Projection::Orthographic { height } => {
+                let half_h = match height {
+                    Some(h) => 0.5 * h,
+                    None => 1.05 * radius * (1.0 / aspect).max(1.0),
+                };
+                let half_w = half_h * aspect;
+                let near = center_depth - radius;
+                (Mat4::orthographic_rh(-half_w, half_w, -half_h, half_h, near, far), near)
+            }
+        };
+
+        let orthographic = matches!(self.projection, Projection::Orthographic { .. });
+        Ok(ResolvedCamera { eye, view, proj, near, far, orthographic })
+    }
+}
+
+/// Distance at which a sphere of `radius` fits a perspective frustum with
+/// vertical field of view `fov_rad` and the given aspect ratio.
+fn fit_sphere(radius: f32, fov_rad: f32, aspect: f32) -> f32 {
+    let half_v = 0.5 * fov_rad;
+    let half_h = (half_v.tan() * aspect).atan();
+    // Small margin so silhouettes don't touch the image border
+    1.05 * radius / half_v.min(half_h).sin()
+}
@@ -0,0 +1,217 @@
+use crate::errors::VulkanRError;
+use crate::output::srgb_to_linear;
+
+/// Color at a relative position along a palette. `position` is in [0, 1]
+/// and `color` is sRGB in [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorStop {
+    pub position: f32,
+    pub color: [f32; 3],
+}
+
+/// Elevation color ramp.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Palette {
+    Grayscale,
+    Viridis,
+    /// Green lowlands through tan to white peaks.
+    Terrain,
+    /// Classic atlas tints: greens, yellow, browns, rock and snow.
+    Hypsometric,
+    /// Deep to shallow water blues.
+    Bathymetry,
+    /// User stops, sorted by increasing position.
+    Custom(Vec<ColorStop>),
+}
+
+/// Elevation range mapped onto the palette. Values outside it are clamped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Domain {
+    /// Minimum and maximum of the data.
+    Range,
+    /// Data quantiles at the two probabilities.
+    Quantile(f32, f32),
+    /// Explicit elevations, in the units of the unscaled data.
+    Fixed(f32, f32),
+}
+
+/// Palette plus domain, resolved against the height data per render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colormap {
+    pub palette: Palette,
+    pub domain: Domain,
+}
+
+impl Default for Colormap {
+    fn default() -> Self {
+        Self { palette: Palette::Grayscale, domain: Domain::Range }
+    }
+}
+
+const VIRIDIS: &[u32] = &[
+    0x440154, 0x472d7b, 0x3b528b, 0x2c728e, 0x21908c, 0x27ad81, 0x5dc863, 0xaadc32, 0xfde725,
+];
+const TERRAIN: &[u32] = &[0x00a600, 0x63c600, 0xe6e600, 0xeab64e, 0xeeb99f, 0xf2f2f2];
+const HYPSOMETRIC: &[u32] = &[0x3c8a4f, 0x8cbf5a, 0xe8e27a, 0xd9a45c, 0xa6724b, 0x8c8c8c, 0xffffff];
+const BATHYMETRY: &[u32] = &[0x08306b, 0x08519c, 0x2171b5, 0x4292c6, 0x6baed6, 0x9ecae1, 0xc6dbef];
+
+impl Palette {
+    pub fn parse(name: &str) -> Result<Self, VulkanRError> {
+        match name {
+            "grayscale" | "grey" | "gray" => Ok(Self::Grayscale),
+            "viridis" => Ok(Self::Viridis),
+            "terrain" => Ok(Self::Terrain),
+            "hypsometric" => Ok(Self::Hypsometric),
+            "bathymetry" => Ok(Self::Bathymetry),
+            other => Err(VulkanRError::InvalidInput {
+                param: "palette",
+                reason: format!("unknown palette '{other}'"),
+            }),
+        }
+    }
+
+    /// Custom palette from flat sRGB triples in [0, 1] and optional stop
+    /// positions (evenly spaced when empty).
+    pub fn custom(colors: &[f64], positions: &[f64]) -> Result<Self, VulkanRError> {
+        if colors.len() % 3 != 0 || colors.len() < 6 {
+            return Err(VulkanRError::InvalidInput {
+                param: "palette",
+                reason: "custom palettes need at least two RGB colors".into(),
+            });
+        }
+        let n = colors.len() / 3;
+        if !positions.is_empty() && positions.len() != n {
+            return Err(VulkanRError::InvalidInput {
+                param: "palette_stops",
+                reason: format!("expected {n} positions, got {}", positions.len()),
+            });
+        }
+        if let Some(p) = positions.iter().find(|p| !(0.0..=1.0).contains(*p)) {
+            return Err(VulkanRError::InvalidInput {
+                param: "palette_stops",
+                reason: format!("positions must lie in [0, 1], got {p}"),
+            });
+        }
+        if positions.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(VulkanRError::InvalidInput {
+                param: "palette_stops",
+                reason: "positions must be strictly increasing".into(),
+            });
+        }
+        let stops = colors
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, c)| ColorStop {
+                position: positions.get(i).map_or(i as f32 / (n - 1) as f32, |&p| p as f32),
+                color: [c[0] as f32, c[1] as f32, c[2] as f32],
+            })
+            .collect();
+        Ok(Self::Custom(stops))
+    }
+
+    fn stops(&self) -> Vec<ColorStop> {
+        let even = |hex: &[u32]| {
+            hex.iter()
+                .enumerate()
+                .map(|(i, &h)| ColorStop {
+                    position: i as f32 / (hex.len() - 1) as f32,
+                    color: [(h >> 16) & 0xff, (h >> 8) & 0xff, h & 0xff].map(|c| c as f32 / 255.0),
+                })
+                .collect()
+        };
+        match self {
+            Self::Grayscale => even(&[0x000000, 0xffffff]),
+            Self::Viridis => even(VIRIDIS),
+            Self::Terrain => even(TERRAIN),
+            Self::Hypsometric => even(HYPSOMETRIC),
+            Self::Bathymetry => even(BATHYMETRY),
+            Self::Custom(stops) => stops.clone(),
+        }
+    }
+}
+
+/// Colormap with its domain evaluated for one heightmap.
+#[derive(Debug, Clone)]
+pub struct ColorRamp {
+    stops: Vec<ColorStop>,
+    min: f32,
+    max: f32,
+}
+
+impl Colormap {
+    /// Resolve the domain against the unscaled heights. Non-finite values
+    /// are ignored.
+    pub fn resolve(&self, z_data: &[f32]) -> Result<ColorRamp, VulkanRError> {
+        let (min, max) = match self.domain {
+            Domain::Fixed(lo, hi) => (lo, hi),
+            Domain::Range => {
+                let finite = z_data.iter().copied().filter(|v| v.is_finite());
+                finite.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)))
+            }
+            Domain::Quantile(p_lo, p_hi) => {
+                if !(0.0..=1.0).contains(&p_lo) || !(0.0..=1.0).contains(&p_hi) || p_lo >= p_hi {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "domain_quantile",
+                        reason: format!("must be increasing probabilities in [0, 1], got ({p_lo}, {p_hi})"),
+                    });
+                }
+                let mut sorted: Vec<f32> = z_data.iter().copied().filter(|v| v.is_finite()).collect();
+                sorted.sort_by(f32::total_cmp);
+                (quantile(&sorted, p_lo), quantile(&sorted, p_hi))
+            }
+        };
+        if !(min.is_finite() && max.is_finite() && min <= max) {
+            return Err(VulkanRError::InvalidInput {
+                param: "domain",
+                reason: format!("invalid color domain ({min}, {max})"),
+            });
+        }
+        Ok(ColorRamp { stops: self.palette.stops(), min, max })
+    }
+}
+
+impl ColorRamp {
+    /// Linear RGB color for an unscaled height.
+    pub fn color(&self, z: f32) -> [f32; 3] {
+        let t = if self.max > self.min { (z - self.min) / (self.max - self.min) } else { 0.5 };
+        self.at(t.clamp(0.0, 1.0))
+    }
+
+    /// Heights mapped onto the ends of the palette.
+    pub fn domain(&self) -> (f32, f32) {
+        (self.min, self.max)
+    }
+
+    /// Linear RGB colors at `n >= 2` evenly spaced palette positions from 0
+    /// to 1, for lookup tables on the GPU.
+    pub fn table(&self, n: usize) -> Vec<[f32; 3]> {
+        (0..n).map(|k| self.at(k as f32 / (n - 1) as f32)).collect()
+    }
+
+    fn at(&self, t: f32) -> [f32; 3] {
+        let stops = &self.stops;
+        let i = stops.partition_point(|s| s.position <= t);
+        let srgb = if i == 0 {
+            stops[0].color
+        } else if i == stops.len() {
+            stops[i - 1].color
+        } else {
+            let (a, b) = (&stops[i - 1], &stops[i]);
+            let f = (t - a.position) / (b.position - a.position);
+            [0, 1, 2].map(|c| a.color[c] + f * (b.color[c] - a.color[c]))
+        };
+        srgb.map(srgb_to_linear)
+    }
+}
+
+/// Linearly interpolated quantile of sorted data (R's type 7).
+fn quantile(sorted: &[f32], p: f32) -> f32 {
+    match sorted.len() {
+        0 => f32::NAN,
+        n => {
+            let h = p * (n - 1) as f32;
+            let (lo, hi) = (h.floor() as usize, h.ceil() as usize);
+            sorted[lo] + (h - lo as f32) * (sorted[hi] - sorted[lo])
+        }
+    }
+}
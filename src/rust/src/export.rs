@@ -0,0 +1,248 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use glam::Vec3;
+
+use crate::errors::VulkanRError;
+use crate::mesh::{HeightfieldMesh, VERTEX_FLOATS};
+use crate::output::linear_to_srgb;
+
+/// File format for exported terrain meshes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+    /// Binary glTF 2.0 with normals, linear vertex colors and UVs, y up.
+    Glb,
+    /// Wavefront OBJ with normals, UVs and sRGB vertex colors, y up.
+    Obj,
+    /// Binary little-endian PLY with normals and 8-bit sRGB colors, z up.
+    Ply,
+    /// Binary STL with facet normals, z up.
+    Stl,
+}
+
+impl MeshFormat {
+    /// Pick the format from a file extension.
+    pub fn from_path(path: &str) -> Result<Self, VulkanRError> {
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "glb" => Ok(Self::Glb),
+            "obj" => Ok(Self::Obj),
+            "ply" => Ok(Self::Ply),
+            "stl" => Ok(Self::Stl),
+            _ => Err(VulkanRError::InvalidInput {
+                param: "path",
+                reason: format!("cannot infer mesh format from extension '{ext}'"),
+            }),
+        }
+    }
+
+    /// Parse an explicit format name; `"auto"` infers it from `path`.
+    pub fn parse(name: &str, path: &str) -> Result<Self, VulkanRError> {
+        match name {
+            "auto" => Self::from_path(path),
+            "glb" => Ok(Self::Glb),
+            "obj" => Ok(Self::Obj),
+            "ply" => Ok(Self::Ply),
+            "stl" => Ok(Self::Stl),
+            other => Err(VulkanRError::InvalidInput {
+                param: "format",
+                reason: format!("unknown mesh format '{other}'"),
+            }),
+        }
+    }
+}
+
+fn io_err(path: &str, e: impl std::fmt::Display) -> VulkanRError {
+    VulkanRError::Io(format!("Failed to save mesh to {}: {}", path, e))
+}
+
+/// Write a mesh to `path` with positions multiplied by `scale`. Scene axes
+/// are kept for y-up formats; z-up formats map scene `(x, y, z)` to
+/// `(x, -z, y)` so north stays +y. Vertices outside every triangle (missing
+/// heights) are dropped.
+pub fn export_mesh(mesh: &HeightfieldMesh, path: &str, format: MeshFormat, scale: f32) -> Result<(), VulkanRError> {
+    let (vertices, indices) = compact(mesh, scale);
+    let file = File::create(path).map_err(|e| io_err(path, e))?;
+    let mut w = BufWriter::new(file);
+    let written = match format {
+        MeshFormat::Glb => write_glb(&mut w, &vertices, &indices),
+        MeshFormat::Obj => write_obj(&mut w, &vertices, &indices),
+        MeshFormat::Ply => write_ply(&mut w, &vertices, &indices),
+        MeshFormat::Stl => write_stl(&mut w, &vertices, &indices),
+    };
+    written.and_then(|_| w.flush()).map_err(|e| io_err(path, e))
+}
+
+/// Referenced vertices only, renumbered in first-use order, with scaled
+/// positions.
+fn compact(mesh: &HeightfieldMesh, scale: f32) -> (Vec<f32>, Vec<u32>) {
+    let mut remap = vec![u32::MAX; mesh.vertices.len() / VERTEX_FLOATS];
+    let mut vertices = Vec::new();
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    for &k in &mesh.indices {
+        let slot = &mut remap[k as usize];
+        if *slot == u32::MAX {
+            *slot = (vertices.len() / VERTEX_FLOATS) as u32;
+            let v = &mesh.vertices[k as usize * VERTEX_FLOATS..(k as usize + 1) * VERTEX_FLOATS];
+            vertices.extend_from_slice(v);
+            let n = vertices.len();
+            vertices[n - VERTEX_FLOATS..n - VERTEX_FLOATS + 3].iter_mut().for_each(|p| *p *= scale);
+        }
+        indices.push(*slot);
+    }
+    (vertices, indices)
+}
+
+fn z_up(p: &[f32]) -> [f32; 3] {
+    [p[0], -p[2], p[1]]
+}
+
+fn srgb8(c: f32) -> u8 {
+    (linear_to_srgb(c) * 255.0).round() as u8
+}
+
+fn write_glb(w: &mut impl Write, vertices: &[f32], indices: &[u32]) -> std::io::Result<()> {
+    let count = vertices.len() / VERTEX_FLOATS;
+    // Non-interleaved attribute blocks: position, normal, color, uv, indices
+    let mut bin = Vec::with_capacity(count * 11 * 4 + indices.len() * 4);
+    let mut views = Vec::new();
+    for (offset, len) in [(0, 3), (3, 3), (6, 3), (9, 2)] {
+        let start = bin.len();
+        for v in vertices.chunks_exact(VERTEX_FLOATS) {
+            v[offset..offset + len].iter().for_each(|x| bin.extend_from_slice(&x.to_le_bytes()));
+        }
+        views.push((start, bin.len() - start));
+    }
+    let start = bin.len();
+    indices.iter().for_each(|i| bin.extend_from_slice(&i.to_le_bytes()));
+    views.push((start, bin.len() - start));
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let (mut min, mut max) = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+    for v in vertices.chunks_exact(VERTEX_FLOATS) {
+        min = min.min(Vec3::from_slice(&v[..3]));
+        max = max.max(Vec3::from_slice(&v[..3]));
+    }
+    let buffer_views = views
+        .iter()
+        .enumerate()
+        .map(|(i, (offset, len))| {
+            let target = if i == 4 { 34963 } else { 34962 };
+            format!(r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{len},"target":{target}}}"#)
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let json = format!(
+        concat!(
+            r#"{{"asset":{{"version":"2.0","generator":"vulkanR"}},"scene":0,"scenes":[{{"nodes":[0]}}],"#,
+            r#""nodes":[{{"mesh":0,"name":"terrain"}}],"#,
+            r#""meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"COLOR_0":2,"TEXCOORD_0":3}},"indices":4,"material":0}}]}}],"#,
+            r#""materials":[{{"pbrMetallicRoughness":{{"metallicFactor":0,"roughnessFactor":1}}}}],"#,
+            r#""accessors":[{{"bufferView":0,"componentType":5126,"count":{n},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},"#,
+            r#"{{"bufferView":1,"componentType":5126,"count":{n},"type":"VEC3"}},"#,
+            r#"{{"bufferView":2,"componentType":5126,"count":{n},"type":"VEC3"}},"#,
+            r#"{{"bufferView":3,"componentType":5126,"count":{n},"type":"VEC2"}},"#,
+            r#"{{"bufferView":4,"componentType":5125,"count":{ni},"type":"SCALAR"}}],"#,
+            r#""bufferViews":[{views}],"buffers":[{{"byteLength":{len}}}]}}"#
+        ),
+        min.x,
+        min.y,
+        min.z,
+        max.x,
+        max.y,
+        max.z,
+        n = count,
+        ni = indices.len(),
+        views = buffer_views,
+        len = bin.len(),
+    );
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    w.write_all(b"glTF")?;
+    w.write_all(&2u32.to_le_bytes())?;
+    w.write_all(&(total as u32).to_le_bytes())?;
+    w.write_all(&(json.len() as u32).to_le_bytes())?;
+    w.write_all(b"JSON")?;
+    w.write_all(&json)?;
+    w.write_all(&(bin.len() as u32).to_le_bytes())?;
+    w.write_all(b"BIN\0")?;
+    w.write_all(&bin)
+}
+
+fn write_obj(w: &mut impl Write, vertices: &[f32], indices: &[u32]) -> std::io::Result<()> {
+    writeln!(w, "# vulkanR terrain mesh")?;
+    for v in vertices.chunks_exact(VERTEX_FLOATS) {
+        let [r, g, b] = [v[6], v[7], v[8]].map(linear_to_srgb);
+        writeln!(w, "v {} {} {} {r:.4} {g:.4} {b:.4}", v[0], v[1], v[2])?;
+    }
+    for v in vertices.chunks_exact(VERTEX_FLOATS) {
+        writeln!(w, "vn {} {} {}", v[3], v[4], v[5])?;
+    }
+    // OBJ texture rows run bottom to top
+    for v in vertices.chunks_exact(VERTEX_FLOATS) {
+        writeln!(w, "vt {} {}", v[9], 1.0 - v[10])?;
+    }
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0] + 1, tri[1] + 1, tri[2] + 1];
+        writeln!(w, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+    }
+    Ok(())
+}
+
+fn write_ply(w: &mut impl Write, vertices: &[f32], indices: &[u32]) -> std::io::Result<()> {
+    write!(
+        w,
+        concat!(
+            "ply\nformat binary_little_endian 1.0\ncomment vulkanR terrain mesh\n",
+            "element vertex {}\nproperty float x\nproperty float y\nproperty float z\n",
+            "property float nx\nproperty float ny\nproperty float nz\n",
+            "property uchar red\nproperty uchar green\nproperty uchar blue\n",
+            "element face {}\nproperty list uchar int vertex_indices\nend_header\n"
+        ),
+        vertices.len() / VERTEX_FLOATS,
+        indices.len() / 3
+    )?;
+    let mut record = Vec::with_capacity(27);
+    for v in vertices.chunks_exact(VERTEX_FLOATS) {
+        record.clear();
+        for x in z_up(&v[0..3]).into_iter().chain(z_up(&v[3..6])) {
+            record.extend_from_slice(&x.to_le_bytes());
+        }
+        record.extend_from_slice(&[srgb8(v[6]), srgb8(v[7]), srgb8(v[8])]);
+        w.write_all(&record)?;
+    }
+    for tri in indices.chunks_exact(3) {
+        record.clear();
+        record.push(3);
+        tri.iter().for_each(|&i| record.extend_from_slice(&(i as i32).to_le_bytes()));
+        w.write_all(&record)?;
+    }
+    Ok(())
+}
+
+fn write_stl(w: &mut impl Write, vertices: &[f32], indices: &[u32]) -> std::io::Result<()> {
+    let mut header = [0u8; 80];
+    header[..20].copy_from_slice(b"vulkanR terrain mesh");
+    w.write_all(&header)?;
+    w.write_all(&((indices.len() / 3) as u32).to_le_bytes())?;
+    let position = |i: u32| Vec3::from(z_up(&vertices[i as usize * VERTEX_FLOATS..]));
+    let mut record = Vec::with_capacity(50);
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [position(tri[0]), position(tri[1]), position(tri[2])];
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        record.clear();
+        for p in [normal, a, b, c] {
+            p.to_array().iter().for_each(|x| record.extend_from_slice(&x.to_le_bytes()));
+        }
+        record.extend_from_slice(&[0, 0]);
+        w.write_all(&record)?;
+    }
+    Ok(())
+}
@@ -5,77 +5,849 @@ mod renderer;
 mod mesh;
 mod shaders;
 mod errors;
+mod camera;
+mod output;
+mod resample;
+mod colormap;
+mod analysis;
+mod export;
+mod simplify;
+mod lod;
+mod solar;
+mod viewshed;
+mod args;
 
-use renderer::WgpuRenderer;
+use glam::Vec3;
+use image::RgbaImage;
+use analysis::Grid;
+use args::Args;
+#[cfg(feature = "ffi")]
+use args::ArgValue;
+use mesh::HeightfieldMesh;
+use renderer::{AmbientOcclusion, AuxOutputs, CellTint, Overlay, RenderOptions, RenderOutput, WgpuRenderer};
+pub use renderer::Geometry;
+use std::sync::Arc;
+use resample::ResampleFilter;
+pub use mesh::GridSpacing;
+pub use export::MeshFormat;
+pub use colormap::{Colormap, Domain, Palette};
 pub use errors::VulkanRError;
+pub use camera::{Camera, CameraView, Projection};
+pub use output::OutputFormat;
+
+/// Renderer kept alive across calls from R behind an external pointer.
+/// `renderer` is `None` once released.
+#[cfg(feature = "ffi")]
+#[derive(Debug)]
+struct RendererHandle {
+    renderer: Option<WgpuRenderer>,
+}
+
+/// Run `f` with the renderer behind `handle`, or with a fresh one if
+/// `handle` is NULL.
+#[cfg(feature = "ffi")]
+fn with_renderer<T>(
+    handle: &Robj,
+    f: impl FnOnce(&mut WgpuRenderer) -> Result<T, VulkanRError>,
+) -> Result<T, VulkanRError> {
+    if handle.is_null() {
+        return f(&mut WgpuRenderer::new()?);
+    }
+    let mut ptr: ExternalPtr<RendererHandle> = handle.clone().try_into().map_err(|_| VulkanRError::InvalidInput {
+        param: "renderer",
+        reason: "not a vk_renderer handle".into(),
+    })?;
+    match ptr.renderer.as_mut() {
+        Some(renderer) => f(renderer),
+        None => Err(VulkanRError::InvalidInput {
+            param: "renderer",
+            reason: "renderer has been released".into(),
+        }),
+    }
+}
+
+/// Create a renderer that can be reused across calls; a positive
+/// `max_buffer_size` caps its buffers below the adapter's limit
+#[cfg(feature = "ffi")]
+#[extendr]
+fn renderer_new(max_buffer_size: f64) -> Result<ExternalPtr<RendererHandle>, VulkanRError> {
+    let max_buffer_size = (max_buffer_size > 0.0).then_some(max_buffer_size as u64);
+    Ok(ExternalPtr::new(RendererHandle { renderer: Some(WgpuRenderer::with_max_buffer_size(max_buffer_size)?) }))
+}
+
+/// Release the GPU device held by a renderer
+#[cfg(feature = "ffi")]
+#[extendr]
+fn renderer_release(handle: Robj) -> Result<(), VulkanRError> {
+    let mut ptr: ExternalPtr<RendererHandle> = handle.try_into().map_err(|_| VulkanRError::InvalidInput {
+        param: "renderer",
+        reason: "not a vk_renderer handle".into(),
+    })?;
+    ptr.renderer = None;
+    Ok(())
+}
 
 /// Get GPU information
 #[cfg_attr(feature = "ffi", extendr)]
-fn gpu_info() -> Result<String, VulkanRError> {
-    let renderer = WgpuRenderer::new()?;
-    Ok(renderer.get_info())
+fn gpu_info(#[cfg(feature = "ffi")] renderer: Robj) -> Result<String, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    return with_renderer(&renderer, |r| Ok(r.get_info()));
+
+    #[cfg(not(feature = "ffi"))]
+    Ok(WgpuRenderer::new()?.get_info())
 }
 
-/// Render heightmap to PNG
+/// Render heightmap to an image file
 #[cfg_attr(feature = "ffi", extendr)]
 fn render_heightmap(
-    path: &str,
+    // Named `path`, `format`, `quality`, `depth_path` and `normal_path`
+    #[cfg(feature = "ffi")] output: List,
+    #[cfg(not(feature = "ffi"))] output: Args,
     // FFI build: get an R matrix
     #[cfg(feature = "ffi")] z: RMatrix<f64>,
     // no-FFI build: get raw data + dims
     #[cfg(not(feature = "ffi"))] z: Vec<f64>,
     #[cfg(not(feature = "ffi"))] rows: usize,
     #[cfg(not(feature = "ffi"))] cols: usize,
-    width: i32,
-    height: i32,
-    scale_z: f64,
-    fov_deg: f64,
-    sun_dir: Vec<f64>,
+    // Named render options from `.heightmap_args()`
+    #[cfg(feature = "ffi")] opts: List,
+    #[cfg(not(feature = "ffi"))] opts: Args,
+    // FFI build: optional vk_renderer handle (NULL for a one-off renderer)
+    #[cfg(feature = "ffi")] renderer: Robj,
 ) -> Result<(), VulkanRError> {
-    // Prepare z_data + dims in each mode
     #[cfg(feature = "ffi")]
-    let (z_data, rows, cols) = {
-        let z_data: Vec<f32> = z.data().iter().map(|&x| x as f32).collect();
-        (z_data, z.nrows(), z.ncols())
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    #[cfg(feature = "ffi")]
+    let opts = args_from_list(opts, RENDER_OPTIONS)?;
+    let opts = render_options(&opts)?;
+    #[cfg(feature = "ffi")]
+    let output = args_from_list(output, OUTPUT_OPTIONS)?;
+    let path = output.string("path")?;
+    let format = OutputFormat::parse(output.string("format")?, path, output.int("quality")?)?;
+    let (depth_path, normal_path) = (output.string("depth_path")?, output.string("normal_path")?);
+    let aux = AuxOutputs {
+        depth: (!depth_path.is_empty()).then_some(depth_path),
+        normals: (!normal_path.is_empty()).then_some(normal_path),
+    };
+    let output = RenderOutput { path, format, aux };
+    let render = |r: &mut WgpuRenderer| r.render_heightmap(output, &z_data, rows, cols, &opts);
+
+    #[cfg(feature = "ffi")]
+    return with_renderer(&renderer, render);
+
+    #[cfg(not(feature = "ffi"))]
+    render(&mut WgpuRenderer::new()?)
+}
+
+/// Render heightmap to an RGBA8 buffer (row-major, top row first)
+#[cfg_attr(feature = "ffi", extendr)]
+fn render_heightmap_pixels(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    // Named render options from `.heightmap_args()`
+    #[cfg(feature = "ffi")] opts: List,
+    #[cfg(not(feature = "ffi"))] opts: Args,
+    #[cfg(feature = "ffi")] renderer: Robj,
+) -> Result<Vec<u8>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    #[cfg(feature = "ffi")]
+    let opts = args_from_list(opts, RENDER_OPTIONS)?;
+    let opts = render_options(&opts)?;
+    let render = |r: &mut WgpuRenderer| r.render_rgba(&z_data, rows, cols, &opts).map(|img| img.into_raw());
+
+    #[cfg(feature = "ffi")]
+    return with_renderer(&renderer, render);
+
+    #[cfg(not(feature = "ffi"))]
+    render(&mut WgpuRenderer::new()?)
+}
+
+/// Render heightmap color and G-buffer planes: R, G, B, A (0-1), linear
+/// depth, normal x/y/z and position x/y/z, each row-major, top row first,
+/// followed by the number of terrain triangles drawn
+#[cfg_attr(feature = "ffi", extendr)]
+fn render_heightmap_buffers(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    // Named render options from `.heightmap_args()`
+    #[cfg(feature = "ffi")] opts: List,
+    #[cfg(not(feature = "ffi"))] opts: Args,
+    #[cfg(feature = "ffi")] renderer: Robj,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    #[cfg(feature = "ffi")]
+    let opts = args_from_list(opts, RENDER_OPTIONS)?;
+    let opts = render_options(&opts)?;
+    let render = |r: &mut WgpuRenderer| {
+        let (img, gb) = r.render_buffers(&z_data, rows, cols, &opts)?;
+        let n = (gb.width * gb.height) as usize;
+        let mut planes = Vec::with_capacity(11 * n);
+        for c in 0..4 {
+            planes.extend(img.pixels().map(|p| p[c] as f64 / 255.0));
+        }
+        planes.extend(gb.depth.iter().map(|&d| d as f64));
+        for v in [&gb.normals, &gb.positions] {
+            for c in 0..3 {
+                planes.extend(v.iter().skip(c).step_by(3).map(|&x| x as f64));
+            }
+        }
+        planes.push(gb.triangles as f64);
+        Ok(planes)
     };
 
+    #[cfg(feature = "ffi")]
+    return with_renderer(&renderer, render);
+
     #[cfg(not(feature = "ffi"))]
-    let (z_data, rows, cols) = {
-        if z.len() != rows * cols {
+    render(&mut WgpuRenderer::new()?)
+}
+
+/// Export the heightmap as a triangle mesh file for the named `opts` of
+/// `export_heightmap_mesh()`. Positions are in the horizontal units of
+/// `cell_size` / `extent`, or scene units without them.
+/// `base_depth` > 0 closes it into a solid block that must be manifold;
+/// `max_error` > 0 simplifies it within that vertical tolerance
+#[cfg_attr(feature = "ffi", extendr)]
+fn export_heightmap_mesh(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    // Named export options from `export_heightmap_mesh()`
+    #[cfg(feature = "ffi")] opts: List,
+    #[cfg(not(feature = "ffi"))] opts: Args,
+) -> Result<(), VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    #[cfg(feature = "ffi")]
+    let opts = args_from_list(opts, EXPORT_OPTIONS)?;
+    let path = opts.string("path")?;
+    let format = MeshFormat::parse(opts.string("format")?, path)?;
+    let spacing = build_spacing(opts.nums("cell_size")?, opts.nums("extent")?, opts.num("z_units")?)?;
+    let colormap = build_colormap(
+        opts.string("palette")?,
+        opts.nums("palette_colors")?,
+        opts.nums("palette_stops")?,
+        opts.nums("domain")?,
+        opts.nums("domain_quantile")?,
+    )?;
+    let (scale_z, base_depth, max_error) = (opts.num("scale_z")?, opts.num("base_depth")?, opts.num("max_error")?);
+    let ramp = colormap.resolve(&z_data)?;
+    let mut mesh = if max_error > 0.0 {
+        HeightfieldMesh::simplified(&z_data, rows, cols, spacing, scale_z as f32, &ramp, max_error as f32)?
+    } else {
+        HeightfieldMesh::new(&z_data, rows, cols, spacing, scale_z as f32, &ramp)?
+    };
+    if base_depth > 0.0 {
+        // Same units as the heights
+        let (_, _, sz) = spacing.mesh_scale(rows, cols, scale_z as f32)?;
+        mesh.add_base(base_depth as f32 * sz)?;
+        let report = mesh.manifold_report();
+        if !report.is_manifold() {
             return Err(VulkanRError::InvalidInput {
-                param: "z",
-                reason: format!("z length {} != rows*cols {}", z.len(), rows * cols),
+                param: "base_depth",
+                reason: format!("solid is not manifold ({report:?}); fill holes that touch only at corners"),
             });
         }
-        (z.iter().map(|&x| x as f32).collect::<Vec<f32>>(), rows, cols)
+    }
+    export::export_mesh(&mesh, path, format, spacing.units_per_scene(rows, cols)?)
+}
+
+/// Sky-view factor of each cell of `z` (row-major, top row first) as
+/// ambient occlusion, tracing `directions` horizons up to `radius` cells
+#[cfg_attr(feature = "ffi", extendr)]
+fn ambient_occlusion(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    directions: i32,
+    radius: i32,
+    cell_size: Vec<f64>,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    let (dx, dy) = cell_size_arg(&cell_size)?;
+    let grid = Grid::new(&z_data, rows, cols, dx, dy)?;
+    let svf = analysis::sky_view_factor(&grid, directions.max(0) as usize, radius.max(0) as usize)?;
+    Ok(svf.into_iter().map(|v| v as f64).collect())
+}
+
+/// Slope of each cell of `z` in degrees, from the same derivatives as the
+/// rendered normals.
+#[cfg_attr(feature = "ffi", extendr)]
+fn terrain_slope(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    cell_size: Vec<f64>,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    let (dx, dy) = cell_size_arg(&cell_size)?;
+    let grid = Grid::new(&z_data, rows, cols, dx, dy)?;
+    let values = analysis::slope(&grid);
+    Ok(values.into_iter().map(|v| v as f64).collect())
+}
+
+/// Aspect of each cell of `z` in degrees clockwise from north (the top row).
+#[cfg_attr(feature = "ffi", extendr)]
+fn terrain_aspect(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    cell_size: Vec<f64>,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    let (dx, dy) = cell_size_arg(&cell_size)?;
+    let grid = Grid::new(&z_data, rows, cols, dx, dy)?;
+    let values = analysis::aspect(&grid);
+    Ok(values.into_iter().map(|v| v as f64).collect())
+}
+
+/// Hillshade of each cell of `z` in [0, 1], averaged over `azimuth` (degrees
+/// clockwise from north) for a light `altitude` degrees above the horizon.
+#[cfg_attr(feature = "ffi", extendr)]
+fn terrain_hillshade(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    azimuth: Vec<f64>,
+    altitude: f64,
+    cell_size: Vec<f64>,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    let (dx, dy) = cell_size_arg(&cell_size)?;
+    let grid = Grid::new(&z_data, rows, cols, dx, dy)?;
+    let azimuths: Vec<f32> = azimuth.iter().map(|&a| a as f32).collect();
+    let values = analysis::hillshade(&grid, &azimuths, altitude as f32)?;
+    Ok(values.into_iter().map(|v| v as f64).collect())
+}
+
+/// Apparent sun azimuth and elevation in degrees plus the scene `sun_dir`
+/// at each `time` (seconds since 1970-01-01 UTC), five values per time.
+#[cfg_attr(feature = "ffi", extendr)]
+fn sun_position(time: Vec<f64>, latitude: f64, longitude: f64) -> Result<Vec<f64>, VulkanRError> {
+    let mut out = Vec::with_capacity(time.len() * 5);
+    for &t in &time {
+        let sun = solar::solar_position(t, latitude, longitude)?;
+        out.extend_from_slice(&[sun.azimuth_deg, sun.elevation_deg]);
+        out.extend(sun.scene_direction().map(|c| c as f64));
+    }
+    Ok(out)
+}
+
+/// Hours of direct sun, then clear-sky radiation in kWh/m², of each cell of
+/// `z` (row-major, top row first) for the named `opts` of `insolation()`:
+/// `start` and `end` in seconds since 1970-01-01 UTC with the sun sampled
+/// every `step` seconds, on the GPU when `gpu` is TRUE and one is available.
+#[cfg_attr(feature = "ffi", extendr)]
+fn insolation(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    #[cfg(feature = "ffi")] opts: List,
+    #[cfg(not(feature = "ffi"))] opts: Args,
+    #[cfg(feature = "ffi")] renderer: Robj,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    #[cfg(feature = "ffi")]
+    let opts = args_from_list(opts, INSOLATION_OPTIONS)?;
+    let settings = solar::InsolationSettings {
+        start: opts.num("start")?,
+        end: opts.num("end")?,
+        step_seconds: opts.num("step")?,
+        latitude_deg: opts.num("latitude")?,
+        longitude_deg: opts.num("longitude")?,
+        directions: opts.int("directions")?.max(0) as usize,
+        radius: opts.int("radius")?.max(0) as usize,
+    };
+    let (dx, dy) = cell_size_arg(opts.nums("cell_size")?)?;
+    let grid = Grid::new(&z_data, rows, cols, dx, dy)?;
+
+    let sun = if opts.flag("gpu")? {
+        let run = |r: &mut WgpuRenderer| solar::insolation_gpu(r, &grid, &settings);
+        #[cfg(feature = "ffi")]
+        let gpu = with_renderer(&renderer, run);
+        #[cfg(not(feature = "ffi"))]
+        let gpu = WgpuRenderer::new().and_then(|mut r| run(&mut r));
+        match gpu {
+            // No usable GPU: trace the horizons on the CPU instead
+            Err(VulkanRError::DeviceInit(_) | VulkanRError::Capability(_)) => solar::insolation(&grid, &settings)?,
+            sun => sun?,
+        }
+    } else {
+        solar::insolation(&grid, &settings)?
     };
+    Ok(sun.sun_hours.iter().chain(&sun.radiation).map(|&v| v as f64).collect())
+}
 
-    if sun_dir.len() != 3 {
+/// Number of observers that see each cell of `z` (row-major, top row
+/// first), for the named `opts` of `viewshed()`. Observers stand on the
+/// 0-based cells `observer_row` and `observer_col` with eyes
+/// `observer_height` above the ground.
+#[cfg_attr(feature = "ffi", extendr)]
+fn viewshed(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    #[cfg(feature = "ffi")] opts: List,
+    #[cfg(not(feature = "ffi"))] opts: Args,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    #[cfg(feature = "ffi")]
+    let opts = args_from_list(opts, VIEWSHED_OPTIONS)?;
+    let (dx, dy) = cell_size_arg(opts.nums("cell_size")?)?;
+    let grid = Grid::new(&z_data, rows, cols, dx, dy)?;
+    let observers = observers_arg(opts.nums("observer_row")?, opts.nums("observer_col")?, opts.nums("observer_height")?)?;
+    let (target_height, max_distance) = (opts.num("target_height")? as f32, opts.num("max_distance")? as f32);
+    let values = viewshed::viewshed(&grid, &observers, target_height, max_distance)?;
+    Ok(values.into_iter().map(|v| v as f64).collect())
+}
+
+/// Line of sight over `z` from the 0-based cell `from` (`c(row, col)`) to
+/// `to`: visibility as 0 or 1, then the fractional 0-based row and column of
+/// the first obstruction, NaN when the target is visible.
+#[cfg_attr(feature = "ffi", extendr)]
+fn line_of_sight(
+    #[cfg(feature = "ffi")] z: RMatrix<f64>,
+    #[cfg(not(feature = "ffi"))] z: Vec<f64>,
+    #[cfg(not(feature = "ffi"))] rows: usize,
+    #[cfg(not(feature = "ffi"))] cols: usize,
+    from: Vec<i32>,
+    to: Vec<i32>,
+    observer_height: f64,
+    target_height: f64,
+) -> Result<Vec<f64>, VulkanRError> {
+    #[cfg(feature = "ffi")]
+    let (z_data, rows, cols) = z_from_matrix(&z);
+    #[cfg(not(feature = "ffi"))]
+    let z_data = z_from_vec(&z, rows, cols)?;
+
+    // Sight lines don't depend on the horizontal scale
+    let grid = Grid::new(&z_data, rows, cols, 1.0, 1.0)?;
+    let cell = |param: &'static str, cell: &[i32]| match *cell {
+        [i, j] if i >= 0 && j >= 0 => Ok((i as usize, j as usize)),
+        _ => Err(VulkanRError::InvalidInput {
+            param,
+            reason: format!("must be one non-negative (row, col) cell, got {cell:?}"),
+        }),
+    };
+    let (row, col) = cell("from", &from)?;
+    let observer = viewshed::Observer { row, col, height: observer_height as f32 };
+    let to = cell("to", &to)?;
+    let sight = viewshed::line_of_sight(&grid, observer, to, target_height as f32)?;
+    let (row, col) = sight.obstruction.map_or((f64::NAN, f64::NAN), |(i, j)| (i as f64, j as f64));
+    Ok(vec![if sight.visible { 1.0 } else { 0.0 }, row, col])
+}
+
+/// Observers from matching row and column vectors, with heights recycled.
+fn observers_arg(rows: &[f64], cols: &[f64], heights: &[f64]) -> Result<Vec<viewshed::Observer>, VulkanRError> {
+    if rows.is_empty() || rows.len() != cols.len() || rows.iter().chain(cols).any(|&k| !(k >= 0.0 && k.fract() == 0.0)) {
         return Err(VulkanRError::InvalidInput {
-            param: "sun_dir",
-            reason: "must have length 3".into(),
+            param: "observers",
+            reason: "must be non-negative (row, col) cells".into(),
         });
     }
-    let sun_dir_f32 = [sun_dir[0] as f32, sun_dir[1] as f32, sun_dir[2] as f32];
-
-    let mut renderer = WgpuRenderer::new()?;
-    renderer.render_heightmap(
-        path,
-        &z_data,
-        rows,
-        cols,
-        width as u32,
-        height as u32,
-        scale_z as f32,
-        fov_deg as f32,
-        sun_dir_f32,
-    )?;
-    Ok(())
+    if heights.is_empty() {
+        return Err(VulkanRError::InvalidInput {
+            param: "observer_height",
+            reason: "needs at least one height".into(),
+        });
+    }
+    Ok(rows
+        .iter()
+        .zip(cols)
+        .enumerate()
+        .map(|(k, (&row, &col))| viewshed::Observer {
+            row: row as usize,
+            col: col as usize,
+            height: heights[k % heights.len()] as f32,
+        })
+        .collect())
+}
+
+/// Horizontal cell spacing: one value for square cells or `(dx, dy)`.
+fn cell_size_arg(cell_size: &[f64]) -> Result<(f32, f32), VulkanRError> {
+    match *cell_size {
+        [d] => Ok((d as f32, d as f32)),
+        [dx, dy] => Ok((dx as f32, dy as f32)),
+        _ => Err(VulkanRError::InvalidInput {
+            param: "cell_size",
+            reason: format!("must have length 1 or 2, got {}", cell_size.len()),
+        }),
+    }
+}
+
+/// Copy an R matrix into row-major `f32` data, top row first.
+#[cfg(feature = "ffi")]
+fn z_from_matrix(z: &RMatrix<f64>) -> (Vec<f32>, usize, usize) {
+    let (rows, cols) = (z.nrows(), z.ncols());
+    (row_major(z.data(), rows, cols), rows, cols)
+}
+
+/// Copy column-major matrix values, as R stores them, into row-major `f32`
+/// data, top row first, like [`z_from_matrix`].
+#[cfg(not(feature = "ffi"))]
+fn z_from_vec(z: &[f64], rows: usize, cols: usize) -> Result<Vec<f32>, VulkanRError> {
+    if z.len() != rows * cols {
+        return Err(VulkanRError::InvalidInput {
+            param: "z",
+            reason: format!("z length {} != rows*cols {}", z.len(), rows * cols),
+        });
+    }
+    Ok(row_major(z, rows, cols))
+}
+
+/// Transpose column-major values into row-major `f32` data.
+fn row_major(data: &[f64], rows: usize, cols: usize) -> Vec<f32> {
+    (0..rows * cols).map(|k| data[(k % cols) * rows + k / cols] as f32).collect()
+}
+
+/// Names of the render options built by `.heightmap_args()` in R.
+const RENDER_OPTIONS: &[&str] = &[
+    "width", "height", "scale_z", "cell_size", "extent", "z_units", "max_error", "geometry", "lod_error",
+    "fov_deg", "sun_dir", "azimuth", "elevation", "distance", "target", "eye", "orthographic",
+    "ortho_height", "msaa", "supersample", "filter", "palette", "palette_colors", "palette_stops", "domain",
+    "domain_quantile", "overlay", "overlay_width", "overlay_height", "overlay_path", "overlay_alpha",
+    "viewshed", "viewshed_color", "shadow_intensity", "shadow_map_size", "ao", "ao_directions", "ao_radius",
+];
+
+/// Names of the settings of `export_heightmap_mesh()`.
+const EXPORT_OPTIONS: &[&str] = &[
+    "path", "format", "scale_z", "cell_size", "extent", "z_units", "palette", "palette_colors", "palette_stops",
+    "domain", "domain_quantile", "base_depth", "max_error",
+];
+
+/// Names of the settings of `viewshed()`.
+const VIEWSHED_OPTIONS: &[&str] =
+    &["observer_row", "observer_col", "observer_height", "target_height", "max_distance", "cell_size"];
+
+/// Names of the settings of `insolation()`.
+const INSOLATION_OPTIONS: &[&str] =
+    &["start", "end", "step", "latitude", "longitude", "directions", "radius", "cell_size", "gpu"];
+
+/// Names of the file output settings of `render_heightmap()`.
+const OUTPUT_OPTIONS: &[&str] = &["path", "format", "quality", "depth_path", "normal_path"];
+
+/// Named arguments from an R list, limited to the `known` names.
+#[cfg(feature = "ffi")]
+fn args_from_list(list: List, known: &[&str]) -> Result<Args, VulkanRError> {
+    let values = list.iter().map(|(name, value)| {
+        let value = if let Some(s) = value.as_str() {
+            ArgValue::Str(s.to_string())
+        } else if let Some(bytes) = value.as_raw_slice() {
+            ArgValue::Raw(bytes.to_vec())
+        } else if let Some(flag) = value.as_bool() {
+            ArgValue::Flag(flag)
+        } else if let Some(v) = value.as_real_vector() {
+            ArgValue::Num(v)
+        } else if let Some(v) = value.as_integer_vector() {
+            ArgValue::Num(v.into_iter().map(f64::from).collect())
+        } else if value.is_null() {
+            ArgValue::Num(Vec::new())
+        } else {
+            return Err(VulkanRError::InvalidInput {
+                param: "options",
+                reason: format!("option '{name}' has an unsupported type"),
+            });
+        };
+        Ok((name.to_string(), value))
+    });
+    Args::new(known, values.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Parse the named R render options into [`RenderOptions`].
+fn render_options(args: &Args) -> Result<RenderOptions, VulkanRError> {
+    let (width, height) = (args.int("width")?, args.int("height")?);
+    if width <= 0 || height <= 0 {
+        return Err(VulkanRError::InvalidInput {
+            param: "width",
+            reason: format!("image size must be positive, got {width}x{height}"),
+        });
+    }
+    let sun_dir = match *args.nums("sun_dir")? {
+        [x, y, z] => [x as f32, y as f32, z as f32],
+        _ => {
+            return Err(VulkanRError::InvalidInput {
+                param: "sun_dir",
+                reason: "must have length 3".into(),
+            })
+        }
+    };
+    let shadow_map_size = args.int("shadow_map_size")?;
+    if shadow_map_size < 16 {
+        return Err(VulkanRError::InvalidInput {
+            param: "shadow_map_size",
+            reason: format!("must be at least 16, got {shadow_map_size}"),
+        });
+    }
+    let msaa = args.int("msaa")?;
+    if ![1, 2, 4, 8].contains(&msaa) {
+        return Err(VulkanRError::InvalidInput {
+            param: "msaa",
+            reason: format!("must be 1, 2, 4 or 8, got {msaa}"),
+        });
+    }
+    let supersample = args.int("supersample")?;
+    if supersample < 1 {
+        return Err(VulkanRError::InvalidInput {
+            param: "supersample",
+            reason: format!("must be at least 1, got {supersample}"),
+        });
+    }
+    let ao = args.num("ao")?;
+    Ok(RenderOptions {
+        width: width as u32,
+        height: height as u32,
+        scale_z: args.num("scale_z")? as f32,
+        spacing: build_spacing(args.nums("cell_size")?, args.nums("extent")?, args.num("z_units")?)?,
+        max_error: args.num("max_error")? as f32,
+        geometry: Geometry::parse(args.string("geometry")?)?,
+        lod_error: args.num("lod_error")? as f32,
+        camera: build_camera(args)?,
+        sun_dir,
+        msaa: msaa as u32,
+        supersample: supersample as u32,
+        filter: ResampleFilter::parse(args.string("filter")?)?,
+        colormap: build_colormap(
+            args.string("palette")?,
+            args.nums("palette_colors")?,
+            args.nums("palette_stops")?,
+            args.nums("domain")?,
+            args.nums("domain_quantile")?,
+        )?,
+        overlay: build_overlay(
+            args.raw("overlay")?.to_vec(),
+            args.int("overlay_width")?,
+            args.int("overlay_height")?,
+            args.string("overlay_path")?,
+            args.num("overlay_alpha")?,
+        )?,
+        viewshed: build_viewshed(args.nums("viewshed")?, args.nums("viewshed_color")?)?,
+        shadow_intensity: args.num("shadow_intensity")? as f32,
+        shadow_map_size: shadow_map_size as u32,
+        ambient_occlusion: (ao > 0.0).then_some(AmbientOcclusion {
+            directions: args.int("ao_directions")?.max(0) as u32,
+            radius: args.int("ao_radius")?.max(0) as u32,
+            strength: ao as f32,
+        }),
+    })
+}
+
+/// Grid spacing from the flat R arguments: `cell_size` as one or two values,
+/// or `extent` as `(xmin, xmax, ymin, ymax)`. Neither keeps the normalized
+/// square layout.
+fn build_spacing(cell_size: &[f64], extent: &[f64], z_units: f64) -> Result<GridSpacing, VulkanRError> {
+    let z_units = z_units as f32;
+    match (cell_size, extent) {
+        ([], []) if z_units == 1.0 => Ok(GridSpacing::Normalized),
+        ([], []) => Err(VulkanRError::InvalidInput {
+            param: "z_units",
+            reason: "needs cell_size or extent to relate heights to".into(),
+        }),
+        (cell_size, []) => {
+            let (dx, dy) = cell_size_arg(cell_size)?;
+            Ok(GridSpacing::Cells { dx, dy, z_units })
+        }
+        ([], &[xmin, xmax, ymin, ymax]) => Ok(GridSpacing::Extent {
+            width: (xmax - xmin) as f32,
+            height: (ymax - ymin) as f32,
+            z_units,
+        }),
+        ([], _) => Err(VulkanRError::InvalidInput {
+            param: "extent",
+            reason: format!("must be (xmin, xmax, ymin, ymax), got length {}", extent.len()),
+        }),
+        _ => Err(VulkanRError::InvalidInput {
+            param: "cell_size",
+            reason: "give either cell_size or extent, not both".into(),
+        }),
+    }
+}
+
+/// Build a colormap from the flat R arguments. `palette` is a built-in name
+/// or `"custom"` for `palette_colors` (sRGB triples in [0, 1]); empty
+/// `domain` and `domain_quantile` mean the full data range.
+fn build_colormap(
+    palette: &str,
+    palette_colors: &[f64],
+    palette_stops: &[f64],
+    domain: &[f64],
+    domain_quantile: &[f64],
+) -> Result<Colormap, VulkanRError> {
+    let palette = match palette {
+        "custom" => Palette::custom(palette_colors, palette_stops)?,
+        name => Palette::parse(name)?,
+    };
+    let domain = match (domain, domain_quantile) {
+        ([], []) => Domain::Range,
+        (&[lo, hi], []) => Domain::Fixed(lo as f32, hi as f32),
+        ([], &[lo, hi]) => Domain::Quantile(lo as f32, hi as f32),
+        _ => {
+            return Err(VulkanRError::InvalidInput {
+                param: "domain",
+                reason: "give either a length-2 domain or domain_quantile, not both".into(),
+            })
+        }
+    };
+    Ok(Colormap { palette, domain })
+}
+
+/// Build the draped overlay from either RGBA bytes (row-major, top row
+/// first) or an image file. Empty bytes and path mean no overlay.
+fn build_overlay(
+    rgba: Vec<u8>,
+    width: i32,
+    height: i32,
+    path: &str,
+    opacity: f64,
+) -> Result<Option<Overlay>, VulkanRError> {
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(VulkanRError::InvalidInput {
+            param: "overlay_alpha",
+            reason: format!("must be in [0, 1], got {opacity}"),
+        });
+    }
+    let image = if !path.is_empty() {
+        image::open(path)
+            .map_err(|e| VulkanRError::Io(format!("Failed to read overlay image {}: {}", path, e)))?
+            .to_rgba8()
+    } else if rgba.is_empty() {
+        return Ok(None);
+    } else {
+        let expected = 4 * width.max(0) as usize * height.max(0) as usize;
+        let got = rgba.len();
+        RgbaImage::from_raw(width.max(0) as u32, height.max(0) as u32, rgba)
+            .filter(|_| got == expected && expected > 0)
+            .ok_or_else(|| VulkanRError::InvalidInput {
+                param: "overlay",
+                reason: format!("expected {expected} RGBA bytes for {width}x{height}, got {got}"),
+            })?
+    };
+    Ok(Some(Overlay { image: Arc::new(image), opacity: opacity as f32 }))
+}
+
+/// Tint over the cells of `viewshed` (row-major, top row first) above 0,
+/// such as a viewshed count. Empty means no tint. `color` is sRGB and alpha
+/// in [0, 1].
+fn build_viewshed(viewshed: &[f64], color: &[f64]) -> Result<Option<CellTint>, VulkanRError> {
+    if viewshed.is_empty() {
+        return Ok(None);
+    }
+    if color.len() != 4 || color.iter().any(|c| !(0.0..=1.0).contains(c)) {
+        return Err(VulkanRError::InvalidInput {
+            param: "viewshed_color",
+            reason: format!("must be RGBA in [0, 1], got {color:?}"),
+        });
+    }
+    Ok(Some(CellTint {
+        cells: Arc::new(viewshed.iter().map(|&v| v > 0.0).collect()),
+        color: [0, 1, 2, 3].map(|c| (color[c] * 255.0).round() as u8),
+    }))
+}
+
+/// Optional 3-vector argument: empty means "not given".
+fn vec3_arg(param: &'static str, v: &[f64]) -> Result<Option<Vec3>, VulkanRError> {
+    match v.len() {
+        0 => Ok(None),
+        3 => Ok(Some(Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32))),
+        n => Err(VulkanRError::InvalidInput {
+            param,
+            reason: format!("must have length 3 (or be empty), got {n}"),
+        }),
+    }
+}
+
+/// Build a camera from the named render options. Non-positive `distance`
+/// and `ortho_height` mean "fit the scene"; a non-empty `eye` selects the
+/// look-at form instead of the orbit parameters.
+fn build_camera(args: &Args) -> Result<Camera, VulkanRError> {
+    let target = vec3_arg("target", args.nums("target")?)?;
+    let camera = match vec3_arg("eye", args.nums("eye")?)? {
+        Some(eye) => Camera::look_at_terrain(eye, target.unwrap_or(Vec3::ZERO)),
+        None => {
+            let distance = args.num("distance")?;
+            Camera::orbit(
+                args.num("azimuth")? as f32,
+                args.num("elevation")? as f32,
+                (distance > 0.0).then_some(distance as f32),
+                target,
+            )
+        }
+    };
+    let projection = if args.flag("orthographic")? {
+        let ortho_height = args.num("ortho_height")?;
+        Projection::Orthographic { height: (ortho_height > 0.0).then_some(ortho_height as f32) }
+    } else {
+        Projection::Perspective { fov_deg: args.num("fov_deg")? as f32 }
+    };
+    let camera = camera.with_projection(projection);
+    camera.validate()?;
+    Ok(camera)
 }
 
 #[cfg(feature = "ffi")]
 extendr_module! {
     mod vulkanR;
+    fn renderer_new;
+    fn renderer_release;
     fn gpu_info;
     fn render_heightmap;
+    fn render_heightmap_pixels;
+    fn render_heightmap_buffers;
+    fn export_heightmap_mesh;
+    fn ambient_occlusion;
+    fn terrain_slope;
+    fn terrain_aspect;
+    fn terrain_hillshade;
+    fn sun_position;
+    fn insolation;
+    fn viewshed;
+    fn line_of_sight;
 }
@@ -0,0 +1,374 @@
+use glam::{Vec3, Vec4};
+
+use crate::analysis::{map_cells, Grid};
+use crate::camera::ResolvedCamera;
+use crate::errors::VulkanRError;
+
+/// Cells along each side of a quadtree tile at every level; coarser tiles
+/// cover more of the grid by sampling every `step`-th point.
+const TILE_CELLS: usize = 32;
+
+/// Quadtree tiles chosen for one view, as grid geometry.
+#[derive(Debug, Default)]
+pub struct Selection {
+    /// Triangles as row-major grid indices, wound counter-clockwise when
+    /// seen from above like [`crate::mesh::HeightfieldMesh::new`].
+    pub triangles: Vec<[u32; 3]>,
+    /// Vertical strips hanging from tile edges that meet a tile of another
+    /// level, hiding the cracks between their different samplings.
+    pub skirts: Vec<Skirt>,
+}
+
+/// Strip under a polyline of grid points along a tile edge.
+#[derive(Debug)]
+pub struct Skirt {
+    /// Row-major grid indices along the edge. Segments touching a missing
+    /// height are left out.
+    pub points: Vec<u32>,
+    /// How far the strip reaches below the edge, in the units of the heights.
+    pub depth: f32,
+}
+
+/// View a level-of-detail mesh is built for: tiles may be off from the full
+/// grid by up to `pixel_error` pixels on a viewport `viewport_height` pixels
+/// tall seen through `camera`.
+#[derive(Debug, Clone, Copy)]
+pub struct LodView<'a> {
+    pub camera: &'a ResolvedCamera,
+    pub viewport_height: u32,
+    pub pixel_error: f32,
+}
+
+/// Pick quadtree tiles for a view so that none is off from the full grid
+/// by more than its pixel error on screen. A tile's error is the largest vertical
+/// distance between its triangles and the grid points they cover, in the
+/// units of the heights; `sz` converts it to scene units, where the grid
+/// lies as in [`crate::mesh::HeightfieldMesh::new`]. Tiles outside the view
+/// are kept coarse. Missing heights force full resolution around them so
+/// holes match the full mesh.
+pub fn select(grid: &Grid, sz: f32, lod: LodView) -> Result<Selection, VulkanRError> {
+    let LodView { camera, viewport_height, pixel_error } = lod;
+    if !(pixel_error > 0.0 && pixel_error.is_finite()) {
+        return Err(VulkanRError::InvalidInput {
+            param: "lod_error",
+            reason: format!("must be a positive number, got {pixel_error}"),
+        });
+    }
+    let tree = Quadtree::new(grid);
+    let view = View { grid, sz, camera, pixels: 0.5 * viewport_height as f32 * camera.proj.y_axis.y.abs() };
+    let mut tiles = Vec::new();
+    tree.select(0, 0, 0, &view, pixel_error, &mut tiles);
+
+    let mut selection = Selection::default();
+    for &tile in &tiles {
+        tree.triangles(tile, &mut selection.triangles);
+    }
+    tree.skirts(&tiles, &mut selection.skirts);
+    if selection.triangles.is_empty() {
+        return Err(VulkanRError::InvalidInput {
+            param: "z",
+            reason: "no grid cell has three finite corner heights to triangulate".into(),
+        });
+    }
+    Ok(selection)
+}
+
+/// Error bound and height range of one quadtree node.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    error: f32,
+    min: f32,
+    max: f32,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Node {
+    /// Node without a finite height; it draws nothing.
+    const EMPTY: Self = Self { error: 0.0, min: f32::INFINITY, max: f32::NEG_INFINITY };
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+}
+
+/// Nodes of one quadtree level, row-major.
+struct Level {
+    step: usize,
+    rows: usize,
+    cols: usize,
+    nodes: Vec<Node>,
+}
+
+/// Tile `(level, node row, node column)`.
+type TileId = (usize, usize, usize);
+
+struct Quadtree<'a> {
+    grid: &'a Grid<'a>,
+    /// From the single root node down to full resolution (step 1).
+    levels: Vec<Level>,
+}
+
+/// Camera terms for the screen-space error.
+struct View<'a> {
+    grid: &'a Grid<'a>,
+    sz: f32,
+    camera: &'a ResolvedCamera,
+    /// Pixels per scene unit at unit depth (perspective) or anywhere
+    /// (orthographic).
+    pixels: f32,
+}
+
+impl<'a> Quadtree<'a> {
+    /// Bound the error of every node, from the finest level up. A node's
+    /// error is at least that of its children, so refining never makes a
+    /// region less accurate.
+    fn new(grid: &'a Grid<'a>) -> Self {
+        let span = (grid.rows.max(grid.cols) - 1).div_ceil(TILE_CELLS).next_power_of_two();
+        let mut levels: Vec<Level> = Vec::new();
+        let mut step = 1;
+        while step <= span {
+            let rows = (grid.rows - 1).div_ceil(TILE_CELLS * step);
+            let cols = (grid.cols - 1).div_ceil(TILE_CELLS * step);
+            let finer = levels.last();
+            let nodes = map_cells(rows, cols, |ni, nj| {
+                let node = node_bounds(grid, (ni * TILE_CELLS * step, nj * TILE_CELLS * step), step);
+                let Some(finer) = finer else { return node };
+                // Fold in the children so errors grow monotonically upwards
+                let children = [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(di, dj)| (2 * ni + di, 2 * nj + dj));
+                children
+                    .iter()
+                    .filter(|&&(ci, cj)| ci < finer.rows && cj < finer.cols)
+                    .fold(node, |acc, &(ci, cj)| Node { error: acc.error.max(finer.nodes[ci * finer.cols + cj].error), ..acc })
+            });
+            levels.push(Level { step, rows, cols, nodes });
+            step *= 2;
+        }
+        levels.reverse();
+        Self { grid, levels }
+    }
+
+    fn node(&self, (level, ni, nj): TileId) -> Node {
+        let level = &self.levels[level];
+        level.nodes[ni * level.cols + nj]
+    }
+
+    /// Sampled grid rows and columns of a tile, clamped to the grid edge.
+    fn lattice(&self, (level, ni, nj): TileId) -> (Vec<usize>, Vec<usize>) {
+        let step = self.levels[level].step;
+        let axis = |origin: usize, len: usize| -> Vec<usize> {
+            let mut points: Vec<usize> =
+                (0..=TILE_CELLS).map(|k| (origin + k * step).min(len - 1)).collect();
+            points.dedup();
+            points
+        };
+        (axis(ni * TILE_CELLS * step, self.grid.rows), axis(nj * TILE_CELLS * step, self.grid.cols))
+    }
+
+    /// Refine the node at `(level, ni, nj)` while its screen-space error
+    /// exceeds `pixel_error`, collecting the tiles to draw.
+    fn select(&self, level: usize, ni: usize, nj: usize, view: &View, pixel_error: f32, out: &mut Vec<TileId>) {
+        let node = self.node((level, ni, nj));
+        if node.is_empty() {
+            return;
+        }
+        let (rows, cols) = self.lattice((level, ni, nj));
+        let finest = level + 1 == self.levels.len();
+        // Coarse tiles over missing heights always refine so holes match the
+        // full mesh; elsewhere only visible tiles need the detail
+        let refine = !finest
+            && (node.error.is_infinite()
+                || node.error > 0.0 && view.pixel_error(&node, &rows, &cols) > pixel_error);
+        if !refine {
+            out.push((level, ni, nj));
+            return;
+        }
+        let finer = &self.levels[level + 1];
+        for (ci, cj) in [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(di, dj)| (2 * ni + di, 2 * nj + dj)) {
+            if ci < finer.rows && cj < finer.cols {
+                self.select(level + 1, ci, cj, view, pixel_error, out);
+            }
+        }
+    }
+
+    /// Two triangles per lattice cell, split like the full mesh: along the
+    /// diagonal that avoids a missing corner.
+    fn triangles(&self, tile: TileId, out: &mut Vec<[u32; 3]>) {
+        let (rows, cols) = self.lattice(tile);
+        let cols_n = self.grid.cols;
+        let present = |k: usize| self.grid.z[k].is_finite();
+        for r in rows.windows(2) {
+            for c in cols.windows(2) {
+                let [a, b] = [r[0] * cols_n + c[0], r[0] * cols_n + c[1]];
+                let [d, e] = [r[1] * cols_n + c[0], r[1] * cols_n + c[1]];
+                let quad = if present(d) && present(b) { [[a, d, b], [d, e, b]] } else { [[a, d, e], [a, e, b]] };
+                for tri in quad {
+                    if tri.iter().all(|&k| present(k)) {
+                        out.push(tri.map(|k| k as u32));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Skirts along the edges where a tile meets tiles of another level.
+    /// The two sides of such an edge each stay within their error of the
+    /// grid, so a skirt as deep as both errors together closes the gap.
+    fn skirts(&self, tiles: &[TileId], out: &mut Vec<Skirt>) {
+        // Each selected tile over the blocks of the finest level it covers
+        let finest = self.levels.last().expect("quadtree has levels");
+        let (block_rows, block_cols) = (finest.rows, finest.cols);
+        let mut owner = vec![usize::MAX; block_rows * block_cols];
+        let blocks = |&(level, ni, nj): &TileId| {
+            let step = self.levels[level].step;
+            let rows = ni * step..((ni + 1) * step).min(block_rows);
+            let cols = nj * step..((nj + 1) * step).min(block_cols);
+            (rows, cols)
+        };
+        for (t, tile) in tiles.iter().enumerate() {
+            let (rows, cols) = blocks(tile);
+            for bi in rows {
+                owner[bi * block_cols + cols.start..bi * block_cols + cols.end].fill(t);
+            }
+        }
+
+        for tile in tiles {
+            let (level, ..) = *tile;
+            let node = self.node(*tile);
+            let (rows, cols) = self.lattice(*tile);
+            let (brows, bcols) = blocks(tile);
+            let cols_n = self.grid.cols;
+            let (first_row, last_row) = (rows[0], rows[rows.len() - 1]);
+            let (first_col, last_col) = (cols[0], cols[cols.len() - 1]);
+            // Each edge's grid points and the block row or column beyond it
+            let along_row = |i: usize| cols.iter().map(|&j| (i * cols_n + j) as u32).collect::<Vec<_>>();
+            let along_col = |j: usize| rows.iter().map(|&i| (i * cols_n + j) as u32).collect::<Vec<_>>();
+            let row_blocks = |bi: Option<usize>| match bi {
+                Some(bi) if bi < block_rows => bcols.clone().map(|bj| (bi, bj)).collect(),
+                _ => Vec::new(),
+            };
+            let col_blocks = |bj: Option<usize>| match bj {
+                Some(bj) if bj < block_cols => brows.clone().map(|bi| (bi, bj)).collect(),
+                _ => Vec::new(),
+            };
+            let edges = [
+                (row_blocks(brows.start.checked_sub(1)), along_row(first_row)),
+                (row_blocks(Some(brows.end)), along_row(last_row)),
+                (col_blocks(bcols.start.checked_sub(1)), along_col(first_col)),
+                (col_blocks(Some(bcols.end)), along_col(last_col)),
+            ];
+            for (neighbours, points) in edges {
+                let mut depth: Option<f32> = None;
+                for (bi, bj) in neighbours {
+                    let other = owner[bi * block_cols + bj];
+                    // Empty neighbours draw nothing; same-level ones share
+                    // this edge's samples exactly
+                    if other == usize::MAX || tiles[other].0 == level {
+                        continue;
+                    }
+                    let error = self.node(tiles[other]).error;
+                    depth = Some(depth.map_or(error, |d| d.max(error)));
+                }
+                // Flat tiles without error already meet exactly
+                if let Some(depth) = depth.filter(|&d| node.error + d > 0.0) {
+                    out.push(Skirt { points, depth: node.error + depth });
+                }
+            }
+        }
+    }
+}
+
+impl View<'_> {
+    /// Screen-space size in pixels of a node's error, at the point of its
+    /// bounding box nearest to the camera. Infinite when the box reaches the
+    /// near plane and 0 when it lies outside the view.
+    fn pixel_error(&self, node: &Node, rows: &[usize], cols: &[usize]) -> f32 {
+        let grid = self.grid;
+        let x = |j: usize| (j as f32 - (grid.cols - 1) as f32 / 2.0) * grid.dx;
+        let z = |i: usize| (i as f32 - (grid.rows - 1) as f32 / 2.0) * grid.dy;
+        let (x0, x1) = (x(cols[0]), x(cols[cols.len() - 1]));
+        let (z0, z1) = (z(rows[0]), z(rows[rows.len() - 1]));
+        let (y0, y1) = (node.min * self.sz, node.max * self.sz);
+        let corners = [x0, x1].map(|x| [y0, y1].map(|y| [z0, z1].map(|z| Vec3::new(x, y, z))));
+        let corners = corners.iter().flatten().flatten();
+
+        // Culled when every corner lies beyond the same clip plane
+        let view_proj = self.camera.view_proj();
+        let clip: Vec<_> = corners.clone().map(|&p| view_proj * p.extend(1.0)).collect();
+        let outside = [
+            |c: &Vec4| c.x < -c.w,
+            |c: &Vec4| c.x > c.w,
+            |c: &Vec4| c.y < -c.w,
+            |c: &Vec4| c.y > c.w,
+            |c: &Vec4| c.z < 0.0,
+            |c: &Vec4| c.z > c.w,
+        ];
+        if outside.iter().any(|plane| clip.iter().all(plane)) {
+            return 0.0;
+        }
+
+        let world_error = node.error * self.sz;
+        if self.camera.orthographic {
+            return world_error * self.pixels;
+        }
+        // Nearest view depth of the box is at one of its corners
+        let depth = corners.map(|&p| -(self.camera.view * p.extend(1.0)).z).fold(f32::INFINITY, f32::min);
+        if depth <= self.camera.near {
+            return f32::INFINITY;
+        }
+        world_error * self.pixels / depth
+    }
+}
+
+/// Error and height range of the node at grid `origin` with the given
+/// `step`: the largest distance between a grid point it covers and the
+/// triangles over its lattice. Infinite when some but not all heights are
+/// missing, except at full resolution, which is exact.
+fn node_bounds(grid: &Grid, origin: (usize, usize), step: usize) -> Node {
+    let (last_i, last_j) = (grid.rows - 1, grid.cols - 1);
+    let end_i = (origin.0 + TILE_CELLS * step).min(last_i);
+    let end_j = (origin.1 + TILE_CELLS * step).min(last_j);
+    let mut node = Node::EMPTY;
+    let mut missing = false;
+    for i in origin.0..=end_i {
+        // Lattice rows around i and the position between them
+        let ra = origin.0 + ((i - origin.0) / step).min(TILE_CELLS - 1) * step;
+        let rb = (ra + step).min(last_i);
+        let v = if rb > ra { (i - ra) as f32 / (rb - ra) as f32 } else { 0.0 };
+        for j in origin.1..=end_j {
+            let h = grid.at(i, j);
+            if !h.is_finite() {
+                missing = true;
+                continue;
+            }
+            node.min = node.min.min(h);
+            node.max = node.max.max(h);
+            if step == 1 {
+                continue;
+            }
+            let ca = origin.1 + ((j - origin.1) / step).min(TILE_CELLS - 1) * step;
+            let cb = (ca + step).min(last_j);
+            let u = if cb > ca { (j - ca) as f32 / (cb - ca) as f32 } else { 0.0 };
+            // Same diagonal as the triangles: from (ra, cb) to (rb, ca)
+            let fit = if u + v <= 1.0 {
+                let z00 = grid.at(ra, ca);
+                z00 + u * (grid.at(ra, cb) - z00) + v * (grid.at(rb, ca) - z00)
+            } else {
+                let z11 = grid.at(rb, cb);
+                z11 + (1.0 - u) * (grid.at(rb, ca) - z11) + (1.0 - v) * (grid.at(ra, cb) - z11)
+            };
+            node.error = node.error.max((h - fit).abs());
+        }
+    }
+    if node.is_empty() {
+        return Node::EMPTY;
+    }
+    if missing && step > 1 {
+        node.error = f32::INFINITY;
+    }
+    node
+}
@@ -1,98 +1,534 @@
+use std::collections::{HashMap, HashSet};
+
+use glam::Vec3;
+
+use crate::analysis::{self, Grid};
+use crate::colormap::ColorRamp;
 use crate::errors::VulkanRError;
+use crate::lod::{self, LodView};
+use crate::simplify;
+
+/// Floats per vertex: position (3), normal (3), color (3), uv (2),
+/// ambient occlusion (1).
+pub const VERTEX_FLOATS: usize = 12;
+
+/// Horizontal placement of the heightmap grid in the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GridSpacing {
+    /// Stretch the grid over [-1, 1] on both axes whatever its shape, with
+    /// heights in scene units times `scale_z`.
+    Normalized,
+    /// Cells `dx` apart along columns and `dy` apart along rows, in
+    /// horizontal units; `z_units` converts heights to horizontal units. The
+    /// true aspect ratio is kept and the longest side spans [-1, 1].
+    Cells { dx: f32, dy: f32, z_units: f32 },
+    /// Grid whose cells tile a `width x height` area in horizontal units,
+    /// like a raster extent; equivalent to cells of `width / cols` by
+    /// `height / rows`.
+    Extent { width: f32, height: f32, z_units: f32 },
+}
+
+impl GridSpacing {
+    /// Scene units per column, per row and per unit of height for a grid of
+    /// `rows x cols` with vertical exaggeration `scale_z`.
+    pub fn mesh_scale(&self, rows: usize, cols: usize, scale_z: f32) -> Result<(f32, f32, f32), VulkanRError> {
+        let (last_i, last_j) = ((rows.max(2) - 1) as f32, (cols.max(2) - 1) as f32);
+        match *self {
+            Self::Normalized => Ok((2.0 / last_j, 2.0 / last_i, scale_z)),
+            Self::Extent { width, height, z_units } => {
+                let (dx, dy) = (width / cols as f32, height / rows as f32);
+                Self::Cells { dx, dy, z_units }.mesh_scale(rows, cols, scale_z)
+            }
+            Self::Cells { dx, dy, z_units } => {
+                if !(dx > 0.0 && dx.is_finite() && dy > 0.0 && dy.is_finite()) {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "cell_size",
+                        reason: format!("cell sizes must be positive, got ({dx}, {dy})"),
+                    });
+                }
+                if !(z_units > 0.0 && z_units.is_finite()) {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "z_units",
+                        reason: format!("must be positive, got {z_units}"),
+                    });
+                }
+                let s = 2.0 / (dx * last_j).max(dy * last_i);
+                Ok((dx * s, dy * s, z_units * scale_z * s))
+            }
+        }
+    }
+
+    /// Horizontal units per scene unit, to undo the fit to [-1, 1]. 1 for
+    /// the normalized layout.
+    pub fn units_per_scene(&self, rows: usize, cols: usize) -> Result<f32, VulkanRError> {
+        let (sx, _, _) = self.mesh_scale(rows, cols, 1.0)?;
+        Ok(match *self {
+            Self::Normalized => 1.0,
+            Self::Cells { dx, .. } => dx / sx,
+            Self::Extent { width, .. } => width / cols as f32 / sx,
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct HeightfieldMesh {
     pub vertices: Vec<f32>,
     pub indices: Vec<u32>,
+    /// Row-major grid cell of each surface vertex, in vertex order, or of
+    /// the edge point a level-of-detail skirt vertex hangs from. Vertices
+    /// past the end (solid base) are not on the grid.
+    pub cells: Vec<u32>,
 }
 
 impl HeightfieldMesh {
-    pub fn new(z_data: &[f32], rows: usize, cols: usize, scale_z: f32) -> Result<Self, VulkanRError> {
+    /// Triangulate a row-major height grid. Non-finite heights are holes:
+    /// triangles touching them are left out.
+    pub fn new(
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        spacing: GridSpacing,
+        scale_z: f32,
+        ramp: &ColorRamp,
+    ) -> Result<Self, VulkanRError> {
+        let (grid, sz) = Self::layout(z_data, rows, cols, spacing, scale_z)?;
+        let mut vertices = Vec::with_capacity(rows * cols * VERTEX_FLOATS);
+        let mut indices = Vec::new();
+
+        // Generate vertices with positions, normals, colors, UVs and unoccluded ambient
+        for i in 0..rows {
+            for j in 0..cols {
+                vertices.extend_from_slice(&surface_vertex(&grid, sz, ramp, i, j));
+            }
+        }
+
+        // Generate triangle indices, skipping triangles that touch a missing
+        // (non-finite) height
+        let present = |k: u32| z_data[k as usize].is_finite();
+        for i in 0..(rows - 1) {
+            for j in 0..(cols - 1) {
+                let idx = (i * cols + j) as u32;
+                let next_row = ((i + 1) * cols + j) as u32;
+
+                // Split along the diagonal that avoids a missing corner, so a
+                // quad with one hole still keeps one triangle
+                let quad = if present(next_row) && present(idx + 1) {
+                    [[idx, next_row, idx + 1], [next_row, next_row + 1, idx + 1]]
+                } else {
+                    [[idx, next_row, next_row + 1], [idx, next_row + 1, idx + 1]]
+                };
+                for tri in quad {
+                    if tri.iter().all(|&k| present(k)) {
+                        indices.extend_from_slice(&tri);
+                    }
+                }
+            }
+        }
+        if indices.is_empty() {
+            return Err(no_triangles());
+        }
+
+        Ok(Self { vertices, indices, cells: (0..(rows * cols) as u32).collect() })
+    }
+
+    /// The bounds [`HeightfieldMesh::new`] would report, without building
+    /// the mesh, for renders that displace a grid on the GPU. Fails the same
+    /// way when no cell has three finite corners.
+    pub fn surface_bounds(
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        spacing: GridSpacing,
+        scale_z: f32,
+    ) -> Result<(Vec3, Vec3), VulkanRError> {
+        let (grid, sz) = Self::layout(z_data, rows, cols, spacing, scale_z)?;
+        let present = |i: usize, j: usize| grid.at(i, j).is_finite();
+        let triangulated = (0..rows - 1).any(|i| {
+            (0..cols - 1).any(|j| {
+                [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)].iter().filter(|&&(i, j)| present(i, j)).count() >= 3
+            })
+        });
+        if !triangulated {
+            return Err(no_triangles());
+        }
+        let mut min = Vec3::splat(f32::INFINITY);
+        let mut max = Vec3::splat(f32::NEG_INFINITY);
+        for i in 0..rows {
+            for j in (0..cols).filter(|&j| present(i, j)) {
+                let p = surface_position(&grid, sz, i, j);
+                min = min.min(p);
+                max = max.max(p);
+            }
+        }
+        Ok((min, max))
+    }
+
+    /// Like [`HeightfieldMesh::new`], but with an adaptive triangulation
+    /// that interpolates every grid height within `max_error` (in the units
+    /// of `z_data`). Only the grid points used by a triangle become vertices.
+    pub fn simplified(
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        spacing: GridSpacing,
+        scale_z: f32,
+        ramp: &ColorRamp,
+        max_error: f32,
+    ) -> Result<Self, VulkanRError> {
+        let (grid, sz) = Self::layout(z_data, rows, cols, spacing, scale_z)?;
+        let triangles = simplify::rtin_triangles(&grid, max_error)?;
+        let mut slots: HashMap<u32, u32> = HashMap::new();
+        let mut cells = Vec::new();
+        let mut indices = Vec::with_capacity(triangles.len() * 3);
+        for &k in triangles.iter().flatten() {
+            let slot = *slots.entry(k).or_insert_with(|| {
+                cells.push(k);
+                cells.len() as u32 - 1
+            });
+            indices.push(slot);
+        }
+        let mut vertices = Vec::with_capacity(cells.len() * VERTEX_FLOATS);
+        for &k in &cells {
+            let (i, j) = (k as usize / cols, k as usize % cols);
+            vertices.extend_from_slice(&surface_vertex(&grid, sz, ramp, i, j));
+        }
+        Ok(Self { vertices, indices, cells })
+    }
+
+    /// Like [`HeightfieldMesh::new`], but with quadtree tiles whose detail
+    /// follows the view: each is within the pixel error of `lod` of the
+    /// full grid, so distant terrain gets coarse tiles. Skirts under the
+    /// edges between tiles of different levels close the cracks; they reuse
+    /// the color and normal of the edge they hang from.
+    pub fn with_lod(
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        spacing: GridSpacing,
+        scale_z: f32,
+        ramp: &ColorRamp,
+        lod: LodView,
+    ) -> Result<Self, VulkanRError> {
+        let (grid, sz) = Self::layout(z_data, rows, cols, spacing, scale_z)?;
+        let selection = lod::select(&grid, sz, lod)?;
+        let mut slots: HashMap<u32, u32> = HashMap::new();
+        let mut cells = Vec::new();
+        let mut indices = Vec::with_capacity(selection.triangles.len() * 3);
+        for &k in selection.triangles.iter().flatten() {
+            let slot = *slots.entry(k).or_insert_with(|| {
+                cells.push(k);
+                cells.len() as u32 - 1
+            });
+            indices.push(slot);
+        }
+        let vertex = |k: u32| surface_vertex(&grid, sz, ramp, k as usize / cols, k as usize % cols);
+        let mut vertices = Vec::with_capacity(cells.len() * VERTEX_FLOATS);
+        for &k in &cells {
+            vertices.extend_from_slice(&vertex(k));
+        }
+
+        // Skirt strips, wound both ways since either side may show
+        for skirt in &selection.skirts {
+            let drop = skirt.depth * sz;
+            let mut previous: Option<(u32, u32)> = None;
+            for &k in &skirt.points {
+                if !z_data[k as usize].is_finite() {
+                    previous = None;
+                    continue;
+                }
+                let top = vertex(k);
+                let mut bottom = top;
+                bottom[1] -= drop;
+                let first = cells.len() as u32;
+                vertices.extend_from_slice(&top);
+                vertices.extend_from_slice(&bottom);
+                cells.extend_from_slice(&[k, k]);
+                if let Some((a, a_low)) = previous {
+                    let (b, b_low) = (first, first + 1);
+                    indices.extend_from_slice(&[a, a_low, b, b, a_low, b_low]);
+                    indices.extend_from_slice(&[a, b, a_low, b, b_low, a_low]);
+                }
+                previous = Some((first, first + 1));
+            }
+        }
+        Ok(Self { vertices, indices, cells })
+    }
+
+    /// Validate the grid and size it in scene units: the grid spacing and
+    /// the scene units per unit of height.
+    fn layout<'a>(
+        z_data: &'a [f32],
+        rows: usize,
+        cols: usize,
+        spacing: GridSpacing,
+        scale_z: f32,
+    ) -> Result<(Grid<'a>, f32), VulkanRError> {
         if z_data.len() != rows * cols {
             return Err(VulkanRError::InvalidInput {
                 param: "z",
                 reason: format!("z_data length {} doesn't match rows*cols {}", z_data.len(), rows * cols),
             });
         }
+        let (sx, sy, sz) = spacing.mesh_scale(rows, cols, scale_z)?;
+        Ok((Grid::new(z_data, rows, cols, sx, sy)?, sz))
+    }
+
+    /// Set the per-vertex ambient factor, one value per grid cell in
+    /// row-major order. Non-finite values leave the ambient unoccluded.
+    pub fn set_ambient(&mut self, ao: &[f32]) {
+        for (v, &k) in self.vertices.chunks_exact_mut(VERTEX_FLOATS).zip(&self.cells) {
+            let a = ao[k as usize];
+            v[11] = if a.is_finite() { a } else { 1.0 };
+        }
+    }
 
-        let mut vertices = Vec::new();
+    /// Close the terrain into a solid block: side walls from every open edge
+    /// of the surface down to a flat bottom `base_depth` scene units below
+    /// the lowest vertex, and a bottom cap. Missing cells become holes
+    /// through the block. Triangles keep the surface's counter-clockwise
+    /// outward winding.
+    pub fn add_base(&mut self, base_depth: f32) -> Result<(), VulkanRError> {
+        if !(base_depth > 0.0 && base_depth.is_finite()) {
+            return Err(VulkanRError::InvalidInput {
+                param: "base_depth",
+                reason: format!("must be positive, got {base_depth}"),
+            });
+        }
+        let base_y = self.bounds().0.y - base_depth;
+        let top = self.indices.clone();
+        let vertex = |k: u32| -> [f32; VERTEX_FLOATS] {
+            self.vertices[k as usize * VERTEX_FLOATS..(k as usize + 1) * VERTEX_FLOATS].try_into().unwrap()
+        };
+        let mut added: Vec<f32> = Vec::new();
+        let mut next = (self.vertices.len() / VERTEX_FLOATS) as u32;
+        let mut push = |mut v: [f32; VERTEX_FLOATS], y: Option<f32>, normal: Vec3| {
+            if let Some(y) = y {
+                v[1] = y;
+            }
+            v[3..6].copy_from_slice(&normal.to_array());
+            v[11] = 1.0;
+            added.extend_from_slice(&v);
+            next += 1;
+            next - 1
+        };
         let mut indices = Vec::new();
 
-        // Generate vertices with positions, normals, and colors
-        for i in 0..rows {
-            for j in 0..cols {
-                let idx = i * cols + j;
-                let height = z_data[idx] * scale_z;
-                
-                // Position (normalize to [-1, 1] range)
-                let x = (j as f32 / (cols - 1) as f32) * 2.0 - 1.0;
-                let y = height;
-                let z = (i as f32 / (rows - 1) as f32) * 2.0 - 1.0;
-                
-                // Compute normal using central differences with epsilon guard
-                let epsilon = 1e-6f32;
-                let dx = if j > 0 && j < cols - 1 {
-                    (z_data[i * cols + j + 1] - z_data[i * cols + j - 1]) * scale_z
-                } else if j == 0 {
-                    (z_data[i * cols + j + 1] - z_data[i * cols + j]) * scale_z
-                } else {
-                    (z_data[i * cols + j] - z_data[i * cols + j - 1]) * scale_z
-                };
-                
-                let dz = if i > 0 && i < rows - 1 {
-                    (z_data[(i + 1) * cols + j] - z_data[(i - 1) * cols + j]) * scale_z
-                } else if i == 0 {
-                    (z_data[(i + 1) * cols + j] - z_data[i * cols + j]) * scale_z
-                } else {
-                    (z_data[i * cols + j] - z_data[(i - 1) * cols + j]) * scale_z
-                };
-                
-                // Cross product for normal: (-dx, 2/grid_spacing, -dz)
-                let grid_spacing = 2.0 / ((cols - 1).max(rows - 1) as f32);
-                let normal_x = -dx;
-                let normal_y = 2.0 * grid_spacing;
-                let normal_z = -dz;
-                
-                // Normalize with epsilon guard
-                let length = (normal_x * normal_x + normal_y * normal_y + normal_z * normal_z).sqrt();
-                let inv_length = if length > epsilon { 1.0 / length } else { 0.0 };
-                
-                let nx = normal_x * inv_length;
-                let ny = if length > epsilon { normal_y * inv_length } else { 1.0 };
-                let nz = normal_z * inv_length;
-                
-                // Color based on height (simple grayscale)
-                let color = (height + 1.0) * 0.5; // Normalize to [0, 1]
-                let color = color.clamp(0.0, 1.0);
-                
-                // Add vertex: position (3) + normal (3) + color (3) = 9 floats
-                vertices.extend_from_slice(&[
-                    x, y, z,           // position
-                    nx, ny, nz,        // normal
-                    color, color, color // color
-                ]);
-            }
-        }
-
-        // Generate triangle indices
-        for i in 0..(rows - 1) {
-            for j in 0..(cols - 1) {
-                let idx = (i * cols + j) as u32;
-                let next_row = ((i + 1) * cols + j) as u32;
-                
-                // Two triangles per quad
-                // Triangle 1: bottom-left, top-left, bottom-right
-                indices.extend_from_slice(&[
-                    idx, next_row, idx + 1
-                ]);
-                
-                // Triangle 2: top-left, top-right, bottom-right
-                indices.extend_from_slice(&[
-                    next_row, next_row + 1, idx + 1
-                ]);
+        // Bottom cap: the surface triangles flattened and flipped
+        let mut bottom = HashMap::new();
+        for tri in top.chunks_exact(3) {
+            let mut base = [0u32; 3];
+            for (b, &k) in base.iter_mut().zip(tri) {
+                *b = *bottom.entry(k).or_insert_with(|| push(vertex(k), Some(base_y), Vec3::NEG_Y));
+            }
+            indices.extend_from_slice(&[base[0], base[2], base[1]]);
+        }
+
+        // Walls under open edges, traversed against the surface winding and
+        // in triangle order so that exports are reproducible
+        let edges: Vec<(u32, u32)> =
+            top.chunks_exact(3).flat_map(|t| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])]).collect();
+        let lookup: HashSet<(u32, u32)> = edges.iter().copied().collect();
+        for &(a, b) in &edges {
+            if lookup.contains(&(b, a)) {
+                continue;
+            }
+            let (va, vb) = (vertex(a), vertex(b));
+            let along = Vec3::new(vb[0] - va[0], 0.0, vb[2] - va[2]);
+            let outward = along.cross(Vec3::Y).normalize_or_zero();
+            let [ta, tb] = [push(va, None, outward), push(vb, None, outward)];
+            let [ba, bb] = [push(va, Some(base_y), outward), push(vb, Some(base_y), outward)];
+            indices.extend_from_slice(&[tb, ta, ba, tb, ba, bb]);
+        }
+
+        self.vertices.extend_from_slice(&added);
+        self.indices.extend_from_slice(&indices);
+        Ok(())
+    }
+
+    /// Check that the triangles form closed 2-manifold surfaces, welding
+    /// vertices at identical positions as STL readers do.
+    pub fn manifold_report(&self) -> ManifoldReport {
+        let mut welded = HashMap::new();
+        let ids: Vec<u32> = self
+            .vertices
+            .chunks_exact(VERTEX_FLOATS)
+            .map(|v| {
+                let key = [v[0].to_bits(), v[1].to_bits(), v[2].to_bits()];
+                let n = welded.len() as u32;
+                *welded.entry(key).or_insert(n)
+            })
+            .collect();
+
+        let mut report = ManifoldReport::default();
+        let mut directed: HashMap<(u32, u32), u32> = HashMap::new();
+        // Around each vertex, the edge opposite it in each incident triangle
+        let mut links: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [ids[tri[0] as usize], ids[tri[1] as usize], ids[tri[2] as usize]];
+            if a == b || b == c || c == a {
+                report.degenerate_triangles += 1;
+                continue;
+            }
+            for (p, q, r) in [(a, b, c), (b, c, a), (c, a, b)] {
+                *directed.entry((p, q)).or_default() += 1;
+                links.entry(p).or_default().push((q, r));
+            }
+        }
+        for (&(a, b), &n) in &directed {
+            let back = directed.get(&(b, a)).copied().unwrap_or(0);
+            if n > 1 {
+                report.flipped_edges += 1;
+            } else if back == 0 {
+                report.open_edges += 1;
+            } else if a < b && n + back > 2 {
+                report.non_manifold_edges += 1;
             }
         }
+        for fan in links.values() {
+            // One cycle through all incident triangles
+            let next: HashMap<u32, u32> = fan.iter().copied().collect();
+            let start = fan[0].0;
+            let (mut at, mut steps) = (start, 0);
+            while let Some(&n) = next.get(&at) {
+                at = n;
+                steps += 1;
+                if at == start || steps > fan.len() {
+                    break;
+                }
+            }
+            if at != start || steps != fan.len() {
+                report.non_manifold_vertices += 1;
+            }
+        }
+        report
+    }
 
-        Ok(Self { vertices, indices })
+    /// Axis-aligned bounding box of the vertex positions as `(min, max)`.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let mut min = Vec3::splat(f32::INFINITY);
+        let mut max = Vec3::splat(f32::NEG_INFINITY);
+        // Vertices of missing heights are not part of any triangle
+        for v in self.vertices.chunks_exact(VERTEX_FLOATS).filter(|v| v[1].is_finite()) {
+            let p = Vec3::new(v[0], v[1], v[2]);
+            min = min.min(p);
+            max = max.max(p);
+        }
+        (min, max)
     }
-}
\ No newline at end of file
+
+    /// Split the triangles, in order, into chunks of at most `max_vertices`
+    /// vertices and `max_indices` indices, each numbered from 0. Triangles
+    /// run row by row, so chunks are bands of the grid; vertices on the
+    /// edges between bands are repeated in both and the pieces join without
+    /// cracks. Vertices outside every triangle are dropped. Fails when a
+    /// chunk can't hold a single triangle.
+    pub fn chunks(&self, max_vertices: usize, max_indices: usize) -> Result<Vec<MeshChunk>, VulkanRError> {
+        if max_vertices < 3 || max_indices < 3 {
+            return Err(VulkanRError::InvalidInput {
+                param: "max_vertices",
+                reason: format!("a chunk must hold at least one triangle, got {max_vertices} vertices and {max_indices} indices"),
+            });
+        }
+        let max_indices = max_indices / 3 * 3;
+        let mut remap = vec![u32::MAX; self.vertices.len() / VERTEX_FLOATS];
+        let mut chunks = Vec::new();
+        let mut chunk = MeshChunk::default();
+        let mut used: Vec<u32> = Vec::new();
+        for tri in self.indices.chunks_exact(3) {
+            let new = tri.iter().filter(|&&k| remap[k as usize] == u32::MAX).count();
+            if used.len() + new > max_vertices || chunk.indices.len() + 3 > max_indices {
+                used.drain(..).for_each(|k| remap[k as usize] = u32::MAX);
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            for &k in tri {
+                let slot = &mut remap[k as usize];
+                if *slot == u32::MAX {
+                    *slot = used.len() as u32;
+                    used.push(k);
+                    let k = k as usize;
+                    chunk.vertices.extend_from_slice(&self.vertices[k * VERTEX_FLOATS..(k + 1) * VERTEX_FLOATS]);
+                }
+                chunk.indices.push(*slot);
+            }
+        }
+        if !chunk.indices.is_empty() {
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+}
+
+/// Part of a [`HeightfieldMesh`] with its own vertex numbering, from
+/// [`HeightfieldMesh::chunks`].
+#[derive(Debug, Default)]
+pub struct MeshChunk {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+/// Defects found by [`HeightfieldMesh::manifold_report`]; all zero for a
+/// watertight, consistently wound solid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ManifoldReport {
+    /// Edges used by only one triangle.
+    pub open_edges: usize,
+    /// Edges used more than once in the same direction, i.e. neighbours
+    /// with opposite winding.
+    pub flipped_edges: usize,
+    /// Edges shared by more than two triangles.
+    pub non_manifold_edges: usize,
+    /// Vertices whose triangles do not form a single fan, like two corners
+    /// touching at a point.
+    pub non_manifold_vertices: usize,
+    /// Triangles with two corners at the same position.
+    pub degenerate_triangles: usize,
+}
+
+impl ManifoldReport {
+    pub fn is_manifold(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Scene position of grid point `(i, j)`, with the grid centered on the
+/// origin and heights scaled by `sz`.
+pub(crate) fn surface_position(grid: &Grid, sz: f32, i: usize, j: usize) -> Vec3 {
+    let x = (j as f32 - (grid.cols - 1) as f32 / 2.0) * grid.dx;
+    let z = (i as f32 - (grid.rows - 1) as f32 / 2.0) * grid.dy;
+    Vec3::new(x, grid.at(i, j) * sz, z)
+}
+
+fn no_triangles() -> VulkanRError {
+    VulkanRError::InvalidInput {
+        param: "z",
+        reason: "no grid cell has three finite corner heights to triangulate".into(),
+    }
+}
+
+/// Vertex for grid point `(i, j)`, at [`surface_position`].
+fn surface_vertex(grid: &Grid, sz: f32, ramp: &ColorRamp, i: usize, j: usize) -> [f32; VERTEX_FLOATS] {
+    let (rows, cols) = (grid.rows, grid.cols);
+
+    // Texture coordinates: first row of z at the top of the image
+    let u = j as f32 / (cols - 1) as f32;
+    let v = i as f32 / (rows - 1) as f32;
+
+    let [x, y, z] = surface_position(grid, sz, i, j).to_array();
+
+    // Normal from the same derivatives as the CPU terrain analysis, in
+    // scene units
+    let (gx, gz) = analysis::gradient(grid, i, j);
+    let normal = Vec3::new(-gx * sz, 1.0, -gz * sz).normalize_or_zero();
+    let [nx, ny, nz] = if normal == Vec3::ZERO { [0.0, 1.0, 0.0] } else { normal.to_array() };
+
+    // Color from the unscaled height
+    let [r, g, b] = ramp.color(grid.at(i, j));
+
+    [
+        x, y, z,    // position
+        nx, ny, nz, // normal
+        r, g, b,    // color
+        u, v,       // uv
+        1.0,        // ambient occlusion
+    ]
+}
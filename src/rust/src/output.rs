@@ -0,0 +1,257 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::{ImageBuffer, ImageFormat, Rgb, Rgba, Rgba32FImage, RgbaImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::errors::VulkanRError;
+
+/// Encoded image format for rendered output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 8-bit sRGB PNG with alpha.
+    Png,
+    /// 16-bit sRGB PNG with alpha.
+    Png16,
+    /// 8-bit sRGB JPEG (alpha dropped) with quality 1-100.
+    Jpeg { quality: u8 },
+    /// 8-bit sRGB TIFF with alpha.
+    Tiff,
+    /// 16-bit sRGB TIFF with alpha.
+    Tiff16,
+    /// Linear 32-bit float OpenEXR (uncompressed).
+    Exr,
+}
+
+impl OutputFormat {
+    /// Pick the format from a file extension.
+    pub fn from_path(path: &str) -> Result<Self, VulkanRError> {
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "png" => Ok(Self::Png),
+            "jpg" | "jpeg" => Ok(Self::Jpeg { quality: 90 }),
+            "tif" | "tiff" => Ok(Self::Tiff),
+            "exr" => Ok(Self::Exr),
+            _ => Err(VulkanRError::InvalidInput {
+                param: "path",
+                reason: format!("cannot infer image format from extension '{ext}'"),
+            }),
+        }
+    }
+
+    /// Parse an explicit format name; `"auto"` infers it from `path`.
+    /// `quality` only applies to JPEG.
+    pub fn parse(name: &str, path: &str, quality: i32) -> Result<Self, VulkanRError> {
+        if !(1..=100).contains(&quality) {
+            return Err(VulkanRError::InvalidInput {
+                param: "quality",
+                reason: format!("must be in 1..=100, got {quality}"),
+            });
+        }
+        let format = match name {
+            "auto" => Self::from_path(path)?,
+            "png" => Self::Png,
+            "png16" => Self::Png16,
+            "jpeg" | "jpg" => Self::Jpeg { quality: 90 },
+            "tiff" => Self::Tiff,
+            "tiff16" => Self::Tiff16,
+            "exr" => Self::Exr,
+            other => {
+                return Err(VulkanRError::InvalidInput {
+                    param: "format",
+                    reason: format!("unknown image format '{other}'"),
+                })
+            }
+        };
+        Ok(match format {
+            Self::Jpeg { .. } => Self::Jpeg { quality: quality as u8 },
+            f => f,
+        })
+    }
+
+    /// Whether the format needs more than 8 bits per channel, i.e. the scene
+    /// has to be rendered to a float target.
+    pub fn is_high_precision(&self) -> bool {
+        matches!(self, Self::Png16 | Self::Tiff16 | Self::Exr)
+    }
+}
+
+fn io_err(path: &str, e: impl std::fmt::Display) -> VulkanRError {
+    VulkanRError::Io(format!("Failed to save image to {}: {}", path, e))
+}
+
+/// Save an 8-bit sRGB image in one of the 8-bit formats.
+pub fn save_rgba8(img: &RgbaImage, path: &str, format: OutputFormat) -> Result<(), VulkanRError> {
+    match format {
+        OutputFormat::Png => img.save_with_format(path, ImageFormat::Png).map_err(|e| io_err(path, e)),
+        OutputFormat::Tiff => img.save_with_format(path, ImageFormat::Tiff).map_err(|e| io_err(path, e)),
+        OutputFormat::Jpeg { quality } => {
+            let rgb: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+                let p = img.get_pixel(x, y);
+                Rgb([p[0], p[1], p[2]])
+            });
+            let file = File::create(path).map_err(|e| io_err(path, e))?;
+            let mut writer = BufWriter::new(file);
+            JpegEncoder::new_with_quality(&mut writer, quality)
+                .encode_image(&rgb)
+                .map_err(|e| io_err(path, e))?;
+            writer.flush().map_err(|e| io_err(path, e))
+        }
+        f => Err(VulkanRError::InvalidInput {
+            param: "format",
+            reason: format!("{f:?} needs a high-precision render"),
+        }),
+    }
+}
+
+/// Save a linear float image in one of the high-precision formats. Integer
+/// formats are sRGB encoded, EXR keeps linear values.
+pub fn save_linear(img: &Rgba32FImage, path: &str, format: OutputFormat) -> Result<(), VulkanRError> {
+    let to_u16 = || -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+            let p = img.get_pixel(x, y);
+            let q = |v: f32| (v.clamp(0.0, 1.0) * 65535.0).round() as u16;
+            Rgba([
+                q(linear_to_srgb(p[0])),
+                q(linear_to_srgb(p[1])),
+                q(linear_to_srgb(p[2])),
+                q(p[3]),
+            ])
+        })
+    };
+    match format {
+        OutputFormat::Png16 => to_u16().save_with_format(path, ImageFormat::Png).map_err(|e| io_err(path, e)),
+        OutputFormat::Tiff16 => to_u16().save_with_format(path, ImageFormat::Tiff).map_err(|e| io_err(path, e)),
+        OutputFormat::Exr => {
+            let n = (img.width() * img.height()) as usize;
+            let mut planes: [Vec<f32>; 4] = std::array::from_fn(|_| Vec::with_capacity(n));
+            for p in img.pixels() {
+                for (c, plane) in planes.iter_mut().enumerate() {
+                    plane.push(p[c]);
+                }
+            }
+            let [r, g, b, a] = &planes;
+            write_exr(path, img.width(), img.height(), &[("R", r), ("G", g), ("B", b), ("A", a)])
+        }
+        f => Err(VulkanRError::InvalidInput {
+            param: "format",
+            reason: format!("{f:?} is an 8-bit format"),
+        }),
+    }
+}
+
+/// sRGB transfer function for a linear value in [0, 1].
+pub fn linear_to_srgb(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of [`linear_to_srgb`].
+pub fn srgb_to_linear(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert IEEE 754 half-precision bits to `f32`.
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((bits >> 10) & 0x1f) as i32;
+    let mant = (bits & 0x3ff) as f32;
+    match exp {
+        0 => sign * mant * 2f32.powi(-24),
+        31 if mant == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mant / 1024.0) * 2f32.powi(exp - 15),
+    }
+}
+
+/// Write an uncompressed scanline OpenEXR file with one 32-bit float plane per
+/// channel. Planes are row-major, top row first.
+pub fn write_exr(path: &str, width: u32, height: u32, channels: &[(&str, &[f32])]) -> Result<(), VulkanRError> {
+    let n = width as usize * height as usize;
+    if let Some((name, _)) = channels.iter().find(|(_, d)| d.len() != n) {
+        return Err(VulkanRError::InvalidInput {
+            param: "channels",
+            reason: format!("channel {name} does not have {n} values"),
+        });
+    }
+    // EXR requires channels sorted by name
+    let mut channels = channels.to_vec();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+
+    fn attr(out: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.extend_from_slice(ty.as_bytes());
+        out.push(0);
+        out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        out.extend_from_slice(value);
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut chlist = Vec::new();
+    for (name, _) in &channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    attr(&mut header, "channels", "chlist", &chlist);
+    attr(&mut header, "compression", "compression", &[0]);
+
+    let mut window = Vec::new();
+    for v in [0i32, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    attr(&mut header, "dataWindow", "box2i", &window);
+    attr(&mut header, "displayWindow", "box2i", &window);
+    attr(&mut header, "lineOrder", "lineOrder", &[0]);
+    attr(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attr(&mut header, "screenWindowCenter", "v2f", &[0u8; 8]);
+    attr(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    // One scanline per block: y (4) + size (4) + channel rows
+    let line_bytes = channels.len() * width as usize * 4;
+    let table_end = header.len() + height as usize * 8;
+
+    let file = File::create(path).map_err(|e| io_err(path, e))?;
+    let mut w = BufWriter::new(file);
+    let mut write = |bytes: &[u8]| w.write_all(bytes).map_err(|e| io_err(path, e));
+
+    write(&header)?;
+    for y in 0..height as usize {
+        let offset = (table_end + y * (8 + line_bytes)) as u64;
+        write(&offset.to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(8 + line_bytes);
+    for y in 0..height as usize {
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_bytes as i32).to_le_bytes());
+        for (_, data) in &channels {
+            for v in &data[y * width as usize..(y + 1) * width as usize] {
+                line.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        write(&line)?;
+    }
+    w.flush().map_err(|e| io_err(path, e))
+}
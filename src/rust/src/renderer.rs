@@ -1,23 +1,403 @@
 use wgpu::*;
 use wgpu::util::DeviceExt;
 use glam::{Mat4, Vec3};
-use image::{ImageBuffer, Rgba};
-use std::path::Path;
+use image::imageops::{self, FilterType};
+use image::{Rgba, Rgba32FImage, RgbaImage};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::Arc;
 
-use crate::mesh::HeightfieldMesh;
-use crate::shaders::{VERTEX_SHADER, FRAGMENT_SHADER};
+use crate::analysis::{self, Grid};
+use crate::camera::Camera;
+use crate::colormap::{ColorRamp, Colormap};
+use crate::lod::LodView;
+use crate::mesh::{surface_position, GridSpacing, HeightfieldMesh, VERTEX_FLOATS};
+use crate::output::{self, f16_to_f32, linear_to_srgb, srgb_to_linear, OutputFormat};
+use crate::resample::{self, ResampleFilter};
+use crate::shaders::{VERTEX_SHADER, FRAGMENT_SHADER, SUN_SHADER};
+use crate::solar::SunSample;
 use crate::errors::VulkanRError;
 
+/// Per-render settings for [`WgpuRenderer::render_heightmap`].
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Vertical exaggeration, relative to the horizontal units when
+    /// `spacing` gives cell sizes.
+    pub scale_z: f32,
+    /// Horizontal spacing of the grid.
+    pub spacing: GridSpacing,
+    /// Vertical tolerance, in the units of the heights, for an adaptive
+    /// mesh with fewer triangles. 0 uses two triangles per grid cell.
+    pub max_error: f32,
+    /// How the terrain surface is built and sent to the GPU.
+    pub geometry: Geometry,
+    /// Screen-space tolerance in output pixels for a quadtree
+    /// level-of-detail mesh built for the camera, coarser far away. 0 uses
+    /// the full grid.
+    pub lod_error: f32,
+    pub camera: Camera,
+    pub sun_dir: [f32; 3],
+    /// MSAA sample count (1, 2, 4 or 8).
+    pub msaa: u32,
+    /// Render at this integer multiple of the output resolution and filter
+    /// down with `filter`. 1 disables supersampling.
+    pub supersample: u32,
+    pub filter: ResampleFilter,
+    /// Elevation-to-color mapping for the terrain.
+    pub colormap: Colormap,
+    /// Image draped over the terrain on top of the colormap.
+    pub overlay: Option<Overlay>,
+    /// Cells tinted on top of the colormap and overlay, such as the
+    /// visible cells of a viewshed.
+    pub viewshed: Option<CellTint>,
+    /// How much cast shadows darken the sunlit term, in [0, 1]. 0 skips the
+    /// shadow pass.
+    pub shadow_intensity: f32,
+    /// Side length of the square sun depth map in texels.
+    pub shadow_map_size: u32,
+    /// Horizon-based ambient occlusion baked into the vertices.
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            width: 512,
+            height: 512,
+            scale_z: 1.0,
+            spacing: GridSpacing::Normalized,
+            max_error: 0.0,
+            geometry: Geometry::Mesh,
+            lod_error: 0.0,
+            camera: Camera::default(),
+            sun_dir: [0.6, 0.7, 0.4],
+            msaa: 1,
+            supersample: 1,
+            filter: ResampleFilter::Lanczos3,
+            colormap: Colormap::default(),
+            overlay: None,
+            viewshed: None,
+            shadow_intensity: 0.0,
+            shadow_map_size: 2048,
+            ambient_occlusion: None,
+        }
+    }
+}
+
+/// Terrain geometry path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Geometry {
+    /// Triangle mesh built on the CPU with per-vertex normals and colors,
+    /// simplified when `max_error` is set.
+    Mesh,
+    /// Heights uploaded as an `R32Float` texture and displaced on the GPU:
+    /// an implicit grid is drawn in instanced patches and the vertex shader
+    /// computes normals and colors. The texture is kept for the next render
+    /// of the same heights, so changing only the view, `scale_z` or lighting
+    /// skips the upload.
+    Displacement,
+}
+
+impl Geometry {
+    pub fn parse(name: &str) -> Result<Self, VulkanRError> {
+        match name {
+            "mesh" => Ok(Self::Mesh),
+            "displacement" => Ok(Self::Displacement),
+            other => Err(VulkanRError::InvalidInput {
+                param: "geometry",
+                reason: format!("unknown geometry '{other}'"),
+            }),
+        }
+    }
+}
+
+/// Settings for [`analysis::sky_view_factor`] as ambient occlusion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientOcclusion {
+    /// Number of horizon directions traced per vertex.
+    pub directions: u32,
+    /// Horizon search distance in grid cells.
+    pub radius: u32,
+    /// Blend between unoccluded (0) and full sky-view factor (1) ambient.
+    pub strength: f32,
+}
+
+/// Image draped over the whole grid extent.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    /// sRGB image; its top row lies along the first row of the heightmap.
+    pub image: Arc<RgbaImage>,
+    /// Blend factor over the colormap, multiplied by the image alpha.
+    pub opacity: f32,
+}
+
+/// Flat color over selected grid cells.
+#[derive(Debug, Clone)]
+pub struct CellTint {
+    /// One flag per grid cell, row-major with the top row first.
+    pub cells: Arc<Vec<bool>>,
+    /// sRGB color and alpha of the flagged cells.
+    pub color: [u8; 4],
+}
+
+/// Extra float images written next to the color output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuxOutputs<'a> {
+    /// OpenEXR path for the linear depth buffer.
+    pub depth: Option<&'a str>,
+    /// OpenEXR path for the world-space normal buffer.
+    pub normals: Option<&'a str>,
+}
+
+impl AuxOutputs<'_> {
+    fn any(&self) -> bool {
+        self.depth.is_some() || self.normals.is_some()
+    }
+}
+
+/// Files written by [`WgpuRenderer::render_heightmap`].
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOutput<'a> {
+    /// Color image path and its encoding.
+    pub path: &'a str,
+    pub format: OutputFormat,
+    pub aux: AuxOutputs<'a>,
+}
+
+/// Per-pixel geometry read back alongside the color image. Planes are
+/// row-major with the top row first; pixels where no terrain was drawn are NaN.
+#[derive(Debug, Clone)]
+pub struct GBuffer {
+    pub width: u32,
+    pub height: u32,
+    /// Distance from the eye along the view axis, in scene units.
+    pub depth: Vec<f32>,
+    /// Unit world-space normals, interleaved xyz.
+    pub normals: Vec<f32>,
+    /// Scene-space positions, interleaved xyz.
+    pub positions: Vec<f32>,
+    /// Terrain triangles drawn, level-of-detail skirts included.
+    pub triangles: usize,
+}
+
+impl GBuffer {
+    /// Write the depth plane as a single-channel (`Z`) OpenEXR file.
+    pub fn save_depth(&self, path: &str) -> Result<(), VulkanRError> {
+        output::write_exr(path, self.width, self.height, &[("Z", &self.depth)])
+    }
+
+    /// Write the normals as an RGB OpenEXR file (x, y, z in R, G, B).
+    pub fn save_normals(&self, path: &str) -> Result<(), VulkanRError> {
+        let plane = |c: usize| self.normals.iter().skip(c).step_by(3).copied().collect::<Vec<f32>>();
+        let (x, y, z) = (plane(0), plane(1), plane(2));
+        output::write_exr(path, self.width, self.height, &[("R", &x), ("G", &y), ("B", &z)])
+    }
+}
+
+/// Heightmap geometry uploaded once and drawn by every pass of a render.
+struct Scene {
+    geometry: SceneGeometry,
+    bounds: (Vec3, Vec3),
+    overlay_view: TextureView,
+    overlay_opacity: f32,
+    shadow: ShadowMap,
+}
+
+enum SceneGeometry {
+    /// Mesh pieces, one indexed draw each, sized to the device buffer limit.
+    Mesh(Vec<MeshBuffers>),
+    Displaced(DisplacedGrid),
+}
+
+/// Textures and layout of a [`Geometry::Displacement`] scene.
+struct DisplacedGrid {
+    heights: Arc<TextureView>,
+    /// Linear colors at evenly spaced palette positions.
+    ramp: TextureView,
+    /// Ambient factor per cell, 1x1 without ambient occlusion.
+    ambient: TextureView,
+    rows: usize,
+    cols: usize,
+    /// Scene spacing of columns and rows and scene units per height.
+    scale: (f32, f32, f32),
+    /// Heights at the ends of the palette.
+    domain: (f32, f32),
+}
+
+impl DisplacedGrid {
+    fn instances(&self) -> u32 {
+        ((self.rows - 1).div_ceil(PATCH_CELLS) * (self.cols - 1).div_ceil(PATCH_CELLS)) as u32
+    }
+}
+
+impl Scene {
+    fn kind(&self) -> Geometry {
+        match self.geometry {
+            SceneGeometry::Mesh(_) => Geometry::Mesh,
+            SceneGeometry::Displaced(_) => Geometry::Displacement,
+        }
+    }
+
+    /// Number of triangles [`Scene::draw`] records.
+    fn triangles(&self) -> usize {
+        match &self.geometry {
+            SceneGeometry::Mesh(chunks) => chunks.iter().map(|c| c.index_count as usize / 3).sum(),
+            SceneGeometry::Displaced(grid) => 2 * PATCH_CELLS * PATCH_CELLS * grid.instances() as usize,
+        }
+    }
+
+    /// Record the terrain draws into `pass`.
+    fn draw<'a>(&'a self, pass: &mut RenderPass<'a>) {
+        match &self.geometry {
+            SceneGeometry::Mesh(chunks) => {
+                for chunk in chunks {
+                    pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+                    pass.set_index_buffer(chunk.index_buffer.slice(..), IndexFormat::Uint32);
+                    pass.draw_indexed(0..chunk.index_count, 0, 0..1);
+                }
+            }
+            SceneGeometry::Displaced(grid) => {
+                pass.draw(0..(6 * PATCH_CELLS * PATCH_CELLS) as u32, 0..grid.instances());
+            }
+        }
+    }
+}
+
+/// Cells per side of one instanced patch of a displaced grid.
+const PATCH_CELLS: usize = 64;
+/// Entries in the color lookup table of a displaced grid.
+const RAMP_TABLE_SIZE: usize = 1024;
+
+/// Height texture kept by the renderer for the next displaced render.
+#[derive(Debug)]
+struct HeightTexture {
+    /// Hash of the height bits.
+    fingerprint: u64,
+    rows: usize,
+    cols: usize,
+    view: Arc<TextureView>,
+}
+
+/// Vertex and index buffer of one piece of the mesh.
+struct MeshBuffers {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    index_count: u32,
+}
+
+/// Sun depth map rendered once per scene.
+struct ShadowMap {
+    view: TextureView,
+    /// Orthographic sun view-projection fitted to the scene bounds.
+    light_vp: Mat4,
+    intensity: f32,
+    size: u32,
+}
+
+/// Pixel rectangle of a `full_width x full_height` image covered by one
+/// pass. It may extend past the image edges; the projection is cropped to it
+/// so adjacent tiles line up exactly.
+#[derive(Debug, Clone, Copy)]
+struct Viewport {
+    full_width: u32,
+    full_height: u32,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl Viewport {
+    fn full(opts: &RenderOptions) -> Self {
+        Self { full_width: opts.width, full_height: opts.height, x: 0, y: 0, width: opts.width, height: opts.height }
+    }
+
+    /// Clip-space transform mapping this rectangle onto the whole target.
+    fn crop(&self) -> Mat4 {
+        let (fw, fh) = (self.full_width as f32, self.full_height as f32);
+        let x0 = -1.0 + 2.0 * self.x as f32 / fw;
+        let x1 = -1.0 + 2.0 * (self.x as f32 + self.width as f32) / fw;
+        let y0 = 1.0 - 2.0 * (self.y as f32 + self.height as f32) / fh;
+        let y1 = 1.0 - 2.0 * self.y as f32 / fh;
+        let (sx, sy) = (2.0 / (x1 - x0), 2.0 / (y1 - y0));
+        Mat4::from_cols(
+            [sx, 0.0, 0.0, 0.0].into(),
+            [0.0, sy, 0.0, 0.0].into(),
+            [0.0, 0.0, 1.0, 0.0].into(),
+            [-(x0 + x1) / (x1 - x0), -(y0 + y1) / (y1 - y0), 0.0, 1.0].into(),
+        )
+    }
+}
+
+/// Texels of one render: the color target plus an optional G-buffer.
+struct Frame {
+    color: Vec<u8>,
+    gbuffer: Option<GBuffer>,
+}
+
+/// Render targets a cached pipeline was built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    geometry: Geometry,
+    color_format: TextureFormat,
+    /// Also write normal and position targets (`fs_gbuffer`).
+    gbuffer: bool,
+    sample_count: u32,
+}
+
+const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+const POSITION_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+/// Heightmap pipeline and the bind group layout it was created with.
+#[derive(Debug)]
+struct HeightmapPipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+/// Smallest buffer size limit a renderer accepts: one 64x64 tile of the
+/// widest (16-byte) render target.
+pub const MIN_BUFFER_SIZE: u64 = 64 * 64 * 16;
+
 /// Renderer holding the wgpu device and queue.
+///
+/// Shader modules are compiled once per renderer and pipelines are cached per
+/// render target configuration, so keeping a renderer alive across calls only
+/// pays for per-frame resources.
+#[derive(Debug)]
 pub struct WgpuRenderer {
     pub device: Device,
     pub queue: Queue,
     pub adapter_info: AdapterInfo,
+    adapter: Adapter,
+    vs: ShaderModule,
+    fs: ShaderModule,
+    sampler: Sampler,
+    shadow_sampler: Sampler,
+    pipelines: HashMap<PipelineKey, HeightmapPipeline>,
+    shadow_pipelines: HashMap<Geometry, HeightmapPipeline>,
+    height_cache: Option<HeightTexture>,
 }
 
 impl WgpuRenderer {
     /// Create a new renderer using Vulkan (Windows/Linux) or Metal (macOS).
     pub fn new() -> Result<Self, VulkanRError> {
+        Self::with_max_buffer_size(None)
+    }
+
+    /// Create a renderer whose buffers are at most `max_buffer_size` bytes,
+    /// or the adapter's limit when that is smaller or not given. Larger
+    /// meshes are uploaded in parts and larger images rendered in tiles.
+    pub fn with_max_buffer_size(max_buffer_size: Option<u64>) -> Result<Self, VulkanRError> {
+        if let Some(size) = max_buffer_size.filter(|&size| size < MIN_BUFFER_SIZE) {
+            return Err(VulkanRError::InvalidInput {
+                param: "max_buffer_size",
+                reason: format!("must be at least {MIN_BUFFER_SIZE} bytes, got {size}"),
+            });
+        }
         let instance = Instance::new(InstanceDescriptor {
             backends: if cfg!(target_os = "macos") {
                 Backends::METAL
@@ -35,17 +415,67 @@ impl WgpuRenderer {
         .ok_or_else(|| VulkanRError::DeviceInit("Failed to find suitable GPU adapter".to_string()))?;
 
         let adapter_info = adapter.get_info();
+        // The adapter's own limits, so renders are only tiled when the
+        // hardware needs it
+        let mut limits = adapter.limits();
+        if let Some(size) = max_buffer_size {
+            limits.max_buffer_size = limits.max_buffer_size.min(size);
+        }
 
         let (device, queue) = pollster::block_on(adapter.request_device(
             &DeviceDescriptor {
                 label: Some("vulkanR Device"),
-                required_features: Features::empty(),
-                required_limits: Limits::default(),
+                // Needed for MSAA sample counts beyond WebGPU's 1 and 4
+                required_features: adapter.features() & Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                required_limits: limits,
             },
             None,
         )).map_err(|e| VulkanRError::DeviceInit(format!("Failed to get device: {}", e)))?;
 
-        Ok(Self { device, queue, adapter_info })
+        // Shaders
+        let vs = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("vulkanR VS"),
+            source: ShaderSource::Wgsl(VERTEX_SHADER.into()),
+        });
+        let fs = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("vulkanR FS"),
+            source: ShaderSource::Wgsl(FRAGMENT_SHADER.into()),
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("vulkanR Overlay Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            anisotropy_clamp: 1,
+            ..Default::default()
+        });
+
+        let shadow_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("vulkanR Shadow Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Ok(Self {
+            device,
+            queue,
+            adapter_info,
+            adapter,
+            vs,
+            fs,
+            sampler,
+            shadow_sampler,
+            pipelines: HashMap::new(),
+            shadow_pipelines: HashMap::new(),
+            height_cache: None,
+        })
     }
 
     /// Return a human‑readable adapter string.
@@ -61,82 +491,920 @@ impl WgpuRenderer {
                 self.adapter_info.backend, self.adapter_info.name, dtype)
     }
 
-    /// Render a heightmap mesh to a PNG file offscreen.
+    /// Render a heightmap mesh offscreen and encode it to `output.path`.
+    /// High-precision formats are rendered to a float target. Depth and
+    /// normal buffers are written as OpenEXR when requested in `output.aux`.
     pub fn render_heightmap(
         &mut self,
-        output_path: &str,
+        output: RenderOutput<'_>,
         z_data: &[f32],
         rows: usize,
         cols: usize,
-        width: u32,
-        height: u32,
-        scale_z: f32,
-        fov_deg: f32,
-        sun_dir: [f32; 3],
+        opts: &RenderOptions,
     ) -> Result<(), VulkanRError> {
-        // Build mesh (positions+normals+colors, 9 floats per vertex)
-        let mesh = HeightfieldMesh::new(z_data, rows, cols, scale_z)?;
+        let RenderOutput { path: output_path, format, aux } = output;
+        let scene = self.upload_scene(z_data, rows, cols, opts)?;
+        let gbuffer = if format.is_high_precision() {
+            let (img, gbuffer) = self.color_linear(&scene, opts, aux.any())?;
+            output::save_linear(&img, output_path, format)?;
+            gbuffer
+        } else {
+            let (img, gbuffer) = self.color_rgba8(&scene, opts, aux.any())?;
+            output::save_rgba8(&img, output_path, format)?;
+            gbuffer
+        };
+        if let Some(gbuffer) = gbuffer {
+            if let Some(path) = aux.depth {
+                gbuffer.save_depth(path)?;
+            }
+            if let Some(path) = aux.normals {
+                gbuffer.save_normals(path)?;
+            }
+        }
+        Ok(())
+    }
 
-        // Render target textures
+    /// Render a heightmap mesh offscreen and return the RGBA8 pixels
+    /// (sRGB, rows top to bottom, no row padding).
+    pub fn render_rgba(
+        &mut self,
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        opts: &RenderOptions,
+    ) -> Result<RgbaImage, VulkanRError> {
+        let scene = self.upload_scene(z_data, rows, cols, opts)?;
+        Ok(self.color_rgba8(&scene, opts, false)?.0)
+    }
+
+    /// Render a heightmap mesh to a half-float target and return linear
+    /// RGBA values (rows top to bottom).
+    pub fn render_linear(
+        &mut self,
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        opts: &RenderOptions,
+    ) -> Result<Rgba32FImage, VulkanRError> {
+        let scene = self.upload_scene(z_data, rows, cols, opts)?;
+        Ok(self.color_linear(&scene, opts, false)?.0)
+    }
+
+    /// Render a heightmap mesh and return the RGBA8 pixels together with the
+    /// depth, normal and position buffers.
+    pub fn render_buffers(
+        &mut self,
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        opts: &RenderOptions,
+    ) -> Result<(RgbaImage, GBuffer), VulkanRError> {
+        let scene = self.upload_scene(z_data, rows, cols, opts)?;
+        let (img, gbuffer) = self.color_rgba8(&scene, opts, true)?;
+        let gbuffer = gbuffer.ok_or_else(|| VulkanRError::Capability("G-buffer was not rendered".into()))?;
+        Ok((img, gbuffer))
+    }
+
+    /// Build the terrain geometry for `opts.geometry` and upload it.
+    fn upload_scene(&mut self, z_data: &[f32], rows: usize, cols: usize, opts: &RenderOptions) -> Result<Scene, VulkanRError> {
+        let ramp = opts.colormap.resolve(z_data)?;
+        let ambient = ambient_occlusion(z_data, rows, cols, opts)?;
+        let (geometry, bounds) = match opts.geometry {
+            Geometry::Mesh => {
+                // Build mesh (positions+normals+colors+uvs+ao, 12 floats per vertex)
+                let (mut mesh, bounds) = if opts.lod_error != 0.0 {
+                    if opts.max_error != 0.0 {
+                        return Err(VulkanRError::InvalidInput {
+                            param: "lod_error",
+                            reason: "can't be combined with max_error".into(),
+                        });
+                    }
+                    // Place the camera as for the full grid so the view
+                    // doesn't depend on the tiles picked for it
+                    let bounds = HeightfieldMesh::surface_bounds(z_data, rows, cols, opts.spacing, opts.scale_z)?;
+                    let camera = opts.camera.resolve(bounds, opts.width as f32 / opts.height as f32)?;
+                    let lod = LodView { camera: &camera, viewport_height: opts.height, pixel_error: opts.lod_error };
+                    let mesh = HeightfieldMesh::with_lod(z_data, rows, cols, opts.spacing, opts.scale_z, &ramp, lod)?;
+                    (mesh, bounds)
+                } else if opts.max_error > 0.0 {
+                    let mesh = HeightfieldMesh::simplified(z_data, rows, cols, opts.spacing, opts.scale_z, &ramp, opts.max_error)?;
+                    let bounds = mesh.bounds();
+                    (mesh, bounds)
+                } else {
+                    let mesh = HeightfieldMesh::new(z_data, rows, cols, opts.spacing, opts.scale_z, &ramp)?;
+                    let bounds = mesh.bounds();
+                    (mesh, bounds)
+                };
+                if let Some(ambient) = &ambient {
+                    mesh.set_ambient(ambient);
+                }
+                (SceneGeometry::Mesh(self.upload_mesh(&mesh)?), bounds)
+            }
+            Geometry::Displacement => {
+                if opts.max_error != 0.0 {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "max_error",
+                        reason: "mesh simplification needs geometry = \"mesh\"".into(),
+                    });
+                }
+                if opts.lod_error != 0.0 {
+                    return Err(VulkanRError::InvalidInput {
+                        param: "lod_error",
+                        reason: "level of detail needs geometry = \"mesh\"".into(),
+                    });
+                }
+                let bounds = HeightfieldMesh::surface_bounds(z_data, rows, cols, opts.spacing, opts.scale_z)?;
+                let grid = self.upload_displaced(z_data, rows, cols, opts, &ramp, ambient.as_deref())?;
+                (SceneGeometry::Displaced(grid), bounds)
+            }
+        };
+        let overlay = draped_overlay(opts, rows, cols)?;
+        let overlay_view = self.upload_overlay(overlay.as_ref().map(|(image, _)| image.as_ref()));
+        let mut scene = Scene {
+            geometry,
+            bounds,
+            overlay_view,
+            overlay_opacity: overlay.as_ref().map_or(0.0, |&(_, opacity)| opacity),
+            shadow: self.shadow_placeholder(),
+        };
+        if opts.shadow_intensity != 0.0 {
+            scene.shadow = self.render_shadow_map(&scene, opts)?;
+        }
+        Ok(scene)
+    }
+
+    /// 1x1 depth map bound when shadows are off.
+    fn shadow_placeholder(&self) -> ShadowMap {
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: Some("vulkanR Shadow Placeholder"),
+            size: Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        ShadowMap {
+            view: texture.create_view(&TextureViewDescriptor::default()),
+            light_vp: Mat4::IDENTITY,
+            intensity: 0.0,
+            size: 1,
+        }
+    }
+
+    /// Render the scene depth from the sun with an orthographic frustum
+    /// tightly enclosing the scene bounds.
+    fn render_shadow_map(&mut self, scene: &Scene, opts: &RenderOptions) -> Result<ShadowMap, VulkanRError> {
+        if !(0.0..=1.0).contains(&opts.shadow_intensity) {
+            return Err(VulkanRError::InvalidInput {
+                param: "shadow_intensity",
+                reason: format!("must be in [0, 1], got {}", opts.shadow_intensity),
+            });
+        }
+        let size = opts.shadow_map_size.clamp(1, self.device.limits().max_texture_dimension_2d);
+
+        let (bmin, bmax) = scene.bounds;
+        let center = (bmin + bmax) * 0.5;
+        let radius = ((bmax - bmin).length() * 0.5).max(1e-3);
+        let sun = Vec3::from(opts.sun_dir).normalize();
+        let up = if sun.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+        let view = Mat4::look_at_rh(center + sun * 2.0 * radius, center, up);
+
+        // Fit the light frustum to the bounding box corners in light space
+        let (mut lo, mut hi) = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { bmin.x } else { bmax.x },
+                if i & 2 == 0 { bmin.y } else { bmax.y },
+                if i & 4 == 0 { bmin.z } else { bmax.z },
+            );
+            let p = view.transform_point3(corner);
+            lo = lo.min(p);
+            hi = hi.max(p);
+        }
+        let pad = 1e-3 * radius;
+        let proj = Mat4::orthographic_rh(lo.x - pad, hi.x + pad, lo.y - pad, hi.y + pad, -hi.z - pad, -lo.z + pad);
+        let light_vp = proj * view;
+
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: Some("vulkanR Shadow Map"),
+            size: Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let shadow = ShadowMap { view, light_vp, intensity: opts.shadow_intensity, size };
+
+        let uniforms = pack_uniforms(Mat4::IDENTITY, opts, scene, &shadow);
+        let uniform_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vulkanR Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&uniforms),
+            usage: BufferUsages::UNIFORM,
+        });
+        let geometry = scene.kind();
+        self.ensure_shadow_pipeline(geometry);
+        let sp = &self.shadow_pipelines[&geometry];
+        let mut entries = vec![BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }];
+        if let SceneGeometry::Displaced(grid) = &scene.geometry {
+            entries.push(BindGroupEntry { binding: 5, resource: BindingResource::TextureView(&grid.heights) });
+        }
+        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("vulkanR Shadow Bind Group"),
+            layout: &sp.bind_group_layout,
+            entries: &entries,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("vulkanR Shadow Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("vulkanR Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &shadow.view,
+                    depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: StoreOp::Store }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&sp.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            scene.draw(&mut pass);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(shadow)
+    }
+
+    /// Sun hours and direct energy in Wh/m² of every cell of `grid` summed
+    /// over `suns`, with `normals` the unit upward cell normals in (east,
+    /// north, up). Each sun position gets a depth map of the terrain from
+    /// the shadow pass, against which a compute pass tests every cell with a
+    /// single bilinear depth comparison, sharper than the 3x3 PCF of the
+    /// fragment shader.
+    pub(crate) fn direct_sun(
+        &mut self,
+        grid: &Grid,
+        normals: &[[f32; 3]],
+        suns: &[SunSample],
+    ) -> Result<Vec<[f32; 2]>, VulkanRError> {
+        if !self.adapter.get_downlevel_capabilities().flags.contains(DownlevelFlags::COMPUTE_SHADERS) {
+            return Err(VulkanRError::Capability("the GPU doesn't support compute shaders".into()));
+        }
+        // Cells go to the GPU in chunks that each fit one storage binding
+        let n = grid.rows * grid.cols;
+        let cell_bytes = 8 * std::mem::size_of::<f32>();
+        let limits = self.device.limits();
+        let max_binding = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size) as usize;
+        let chunk_cells = max_binding / cell_bytes;
+        if chunk_cells == 0 {
+            return Err(VulkanRError::OutOfMemory { requested: n * cell_bytes, available: max_binding });
+        }
+
+        // The terrain as rendered, at one scene unit per height unit
+        // horizontally and vertically so the shadows keep their proportions
+        let opts = RenderOptions {
+            spacing: GridSpacing::Cells { dx: grid.dx, dy: grid.dy, z_units: 1.0 },
+            shadow_intensity: 1.0,
+            // About four texels per cell; render_shadow_map caps it at the
+            // device's texture size
+            shadow_map_size: (4 * grid.rows.max(grid.cols)).min(u32::MAX as usize) as u32,
+            ..Default::default()
+        };
+        let mut scene = self.upload_scene(grid.z, grid.rows, grid.cols, &RenderOptions { shadow_intensity: 0.0, ..opts.clone() })?;
+        let (sx, sy, sz) = opts.spacing.mesh_scale(grid.rows, grid.cols, opts.scale_z)?;
+        let scene_grid = Grid::new(grid.z, grid.rows, grid.cols, sx, sy)?;
+        let mut cells = Vec::with_capacity(8 * n);
+        for (k, &[east, north, up]) in normals.iter().enumerate() {
+            let p = surface_position(&scene_grid, sz, k / grid.cols, k % grid.cols);
+            let valid = if p.y.is_finite() { 1.0 } else { 0.0 };
+            cells.extend_from_slice(&[p.x, p.y, p.z, valid, east, up, -north, 0.0]);
+        }
+        // Cell buffer, sun totals buffer and cell count of each chunk
+        let chunks: Vec<(Buffer, Buffer, usize)> = cells
+            .chunks(8 * chunk_cells)
+            .map(|part| {
+                let len = part.len() / 8;
+                let cell_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("vulkanR Sun Cells"),
+                    contents: bytemuck::cast_slice(part),
+                    usage: BufferUsages::STORAGE,
+                });
+                let totals = self.device.create_buffer(&BufferDescriptor {
+                    label: Some("vulkanR Sun Totals"),
+                    size: (len * 2 * std::mem::size_of::<f32>()) as u64,
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                });
+                (cell_buffer, totals, len)
+            })
+            .collect();
+        let uniform_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("vulkanR Sun Uniform Buffer"),
+            size: 24 * std::mem::size_of::<f32>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let module = self.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("vulkanR Sun CS"),
+            source: ShaderSource::Wgsl(SUN_SHADER.into()),
+        });
+        let storage = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = self.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("vulkanR Sun Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+        let layout = self.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("vulkanR Sun Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = self.device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("vulkanR Sun Pipeline"),
+            layout: Some(&layout),
+            module: &module,
+            entry_point: "cs_sun",
+        });
+
+        // 64 cells per workgroup, wrapped into rows of workgroups past the
+        // per-dimension dispatch limit
+        let max_groups = limits.max_compute_workgroups_per_dimension;
+        let dispatch = |len: usize| {
+            let groups = len.div_ceil(64) as u32;
+            (groups.min(max_groups), groups.div_ceil(max_groups))
+        };
+
+        for sun in suns {
+            let [east, north, up] = sun.toward;
+            let opts = RenderOptions { sun_dir: [east, up, -north], ..opts.clone() };
+            scene.shadow = self.render_shadow_map(&scene, &opts)?;
+            let (bmin, bmax) = scene.bounds;
+            // Lift the cells by 3/4 of a texel so that flat ground clears
+            // the filtered depth of its own texels
+            let offset = 0.75 * (bmax - bmin).length() / scene.shadow.size as f32;
+            let mut uniforms = [0.0f32; 24];
+            uniforms[..16].copy_from_slice(&scene.shadow.light_vp.to_cols_array());
+            uniforms[16..20].copy_from_slice(&[east, up, -north, sun.beam]);
+            uniforms[20..22].copy_from_slice(&[sun.hours, offset]);
+            self.queue.write_buffer(&uniform_buffer, 0, bytemuck::cast_slice(&uniforms));
+
+            let bind_groups: Vec<BindGroup> = chunks
+                .iter()
+                .map(|(cell_buffer, totals, _)| {
+                    self.device.create_bind_group(&BindGroupDescriptor {
+                        label: Some("vulkanR Sun Bind Group"),
+                        layout: &bind_group_layout,
+                        entries: &[
+                            BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                            BindGroupEntry { binding: 1, resource: cell_buffer.as_entire_binding() },
+                            BindGroupEntry { binding: 2, resource: totals.as_entire_binding() },
+                            BindGroupEntry { binding: 3, resource: BindingResource::TextureView(&scene.shadow.view) },
+                            BindGroupEntry { binding: 4, resource: BindingResource::Sampler(&self.shadow_sampler) },
+                        ],
+                    })
+                })
+                .collect();
+            let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("vulkanR Sun Encoder"),
+            });
+            {
+                let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("vulkanR Sun Pass"),
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(&pipeline);
+                for (bind_group, &(_, _, len)) in bind_groups.iter().zip(&chunks) {
+                    let (groups_x, groups_y) = dispatch(len);
+                    pass.set_bind_group(0, bind_group, &[]);
+                    pass.dispatch_workgroups(groups_x, groups_y, 1);
+                }
+            }
+            self.queue.submit(std::iter::once(encoder.finish()));
+            // Free each depth map before the next one is allocated
+            self.device.poll(Maintain::Wait);
+        }
+
+        let mut out = Vec::with_capacity(n);
+        for (_, totals, _) in &chunks {
+            let readback = self.device.create_buffer(&BufferDescriptor {
+                label: Some("vulkanR Sun Readback"),
+                size: totals.size(),
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("vulkanR Sun Readback Encoder"),
+            });
+            encoder.copy_buffer_to_buffer(totals, 0, &readback, 0, totals.size());
+            self.queue.submit(std::iter::once(encoder.finish()));
+
+            let slice = readback.slice(..);
+            let (tx, rx) = std::sync::mpsc::channel();
+            slice.map_async(MapMode::Read, move |r| { tx.send(r).unwrap(); });
+            self.device.poll(Maintain::Wait);
+            rx.recv()
+                .map_err(|e| VulkanRError::DeviceInit(format!("Failed to receive from channel: {}", e)))?
+                .map_err(|e| VulkanRError::DeviceInit(format!("Failed to map buffer: {}", e)))?;
+            let data = slice.get_mapped_range();
+            out.extend_from_slice(bytemuck::cast_slice::<u8, [f32; 2]>(&data));
+            drop(data);
+            readback.unmap();
+        }
+        Ok(out)
+    }
+
+    /// Upload the mesh in chunks whose vertex and index buffers each fit the
+    /// device's `max_buffer_size`, drawn one after the other. Fails with
+    /// [`VulkanRError::OutOfMemory`], reporting the bytes the whole mesh
+    /// needs against that limit, when not even one triangle fits a buffer or
+    /// the device runs out of memory.
+    fn upload_mesh(&self, mesh: &HeightfieldMesh) -> Result<Vec<MeshBuffers>, VulkanRError> {
+        let max_bytes = self.device.limits().max_buffer_size as usize;
+        let vertex_bytes = VERTEX_FLOATS * std::mem::size_of::<f32>();
+        let index_bytes = std::mem::size_of::<u32>();
+        let (max_vertices, max_indices) = (max_bytes / vertex_bytes, max_bytes / index_bytes);
+        let requested = mesh.vertices.len() * std::mem::size_of::<f32>() + mesh.indices.len() * index_bytes;
+        let out_of_memory = || VulkanRError::OutOfMemory { requested, available: max_bytes };
+        if max_vertices < 3 || max_indices < 3 {
+            return Err(out_of_memory());
+        }
+
+        let fits = mesh.vertices.len() / VERTEX_FLOATS <= max_vertices && mesh.indices.len() <= max_indices;
+        let chunks = if fits { Vec::new() } else { mesh.chunks(max_vertices, max_indices)? };
+        let parts: Vec<(&[f32], &[u32])> = if fits {
+            vec![(&mesh.vertices, &mesh.indices)]
+        } else {
+            chunks.iter().map(|c| (c.vertices.as_slice(), c.indices.as_slice())).collect()
+        };
+
+        let mut buffers = Vec::with_capacity(parts.len());
+        for (vertices, indices) in parts {
+            self.device.push_error_scope(ErrorFilter::OutOfMemory);
+            let vertex_buffer = self.device.create_buffer(&BufferDescriptor {
+                label: Some("vulkanR Vertex Buffer"),
+                size: std::mem::size_of_val(vertices) as u64,
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let index_buffer = self.device.create_buffer(&BufferDescriptor {
+                label: Some("vulkanR Index Buffer"),
+                size: std::mem::size_of_val(indices) as u64,
+                usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            if pollster::block_on(self.device.pop_error_scope()).is_some() {
+                return Err(out_of_memory());
+            }
+            self.queue.write_buffer(&vertex_buffer, 0, bytemuck::cast_slice(vertices));
+            self.queue.write_buffer(&index_buffer, 0, bytemuck::cast_slice(indices));
+            buffers.push(MeshBuffers { vertex_buffer, index_buffer, index_count: indices.len() as u32 });
+        }
+        Ok(buffers)
+    }
+
+    /// Upload the heights as an `R32Float` texture, reusing the one from the
+    /// previous displaced render when the grid is unchanged, plus the color
+    /// lookup table and ambient occlusion.
+    fn upload_displaced(
+        &mut self,
+        z_data: &[f32],
+        rows: usize,
+        cols: usize,
+        opts: &RenderOptions,
+        ramp: &ColorRamp,
+        ambient: Option<&[f32]>,
+    ) -> Result<DisplacedGrid, VulkanRError> {
+        let max_dim = self.device.limits().max_texture_dimension_2d as usize;
+        if rows > max_dim || cols > max_dim {
+            return Err(VulkanRError::Capability(format!(
+                "a {rows}x{cols} grid exceeds this adapter's texture limit of {max_dim}; use geometry = \"mesh\""
+            )));
+        }
+        let scale = opts.spacing.mesh_scale(rows, cols, opts.scale_z)?;
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        hasher.write(bytemuck::cast_slice(z_data));
+        let fingerprint = hasher.finish();
+        let cached = self
+            .height_cache
+            .as_ref()
+            .filter(|c| c.fingerprint == fingerprint && c.rows == rows && c.cols == cols)
+            .map(|c| Arc::clone(&c.view));
+        let heights = match cached {
+            Some(view) => view,
+            None => {
+                let view = Arc::new(self.upload_texture(
+                    "vulkanR Heights",
+                    TextureFormat::R32Float,
+                    (cols as u32, rows as u32),
+                    bytemuck::cast_slice(z_data),
+                ));
+                self.height_cache = Some(HeightTexture { fingerprint, rows, cols, view: Arc::clone(&view) });
+                view
+            }
+        };
+
+        let table: Vec<f32> = ramp.table(RAMP_TABLE_SIZE).iter().flat_map(|&[r, g, b]| [r, g, b, 1.0]).collect();
+        let ramp_view = self.upload_texture(
+            "vulkanR Color Table",
+            TextureFormat::Rgba32Float,
+            (RAMP_TABLE_SIZE as u32, 1),
+            bytemuck::cast_slice(&table),
+        );
+        let (ambient_size, ambient_texels) = match ambient {
+            Some(ao) => {
+                let texels = ao.iter().map(|&a| if a.is_finite() { (a.clamp(0.0, 1.0) * 255.0).round() as u8 } else { 255 });
+                ((cols as u32, rows as u32), texels.collect())
+            }
+            None => ((1, 1), vec![255]),
+        };
+        let ambient_view = self.upload_texture("vulkanR Ambient", TextureFormat::R8Unorm, ambient_size, &ambient_texels);
+
+        Ok(DisplacedGrid {
+            heights,
+            ramp: ramp_view,
+            ambient: ambient_view,
+            rows,
+            cols,
+            scale,
+            domain: ramp.domain(),
+        })
+    }
+
+    /// Single-level 2D texture of `format` filled with tightly packed rows.
+    fn upload_texture(&self, label: &str, format: TextureFormat, (width, height): (u32, u32), texels: &[u8]) -> TextureView {
+        let size = Extent3d { width, height, depth_or_array_layers: 1 };
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let bytes_per_texel = format.block_copy_size(None).unwrap_or(4);
+        self.queue.write_texture(
+            ImageCopyTexture { texture: &texture, mip_level: 0, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+            texels,
+            ImageDataLayout { offset: 0, bytes_per_row: Some(bytes_per_texel * width), rows_per_image: Some(height) },
+            size,
+        );
+        texture.create_view(&TextureViewDescriptor::default())
+    }
+
+    /// Upload the overlay image with a full mip chain, shrinking it first if
+    /// it exceeds the texture size limit. The smaller levels are filtered in
+    /// linear light, as averaging sRGB values would darken them. Without an
+    /// overlay a 1x1 placeholder keeps the bind group layout fixed.
+    fn upload_overlay(&self, image: Option<&RgbaImage>) -> TextureView {
+        let max_dim = self.device.limits().max_texture_dimension_2d;
+        let mut top: Cow<RgbaImage> =
+            image.map_or_else(|| Cow::Owned(RgbaImage::from_pixel(1, 1, Rgba([255; 4]))), Cow::Borrowed);
+        let mut linear = linear_rgba(&top);
+        if top.width().max(top.height()) > max_dim {
+            let scale = max_dim as f32 / top.width().max(top.height()) as f32;
+            let w = ((top.width() as f32 * scale) as u32).clamp(1, max_dim);
+            let h = ((top.height() as f32 * scale) as u32).clamp(1, max_dim);
+            linear = imageops::resize(&linear, w, h, FilterType::Triangle);
+            top = Cow::Owned(srgb8_image(&linear));
+        }
+
+        let (width, height) = linear.dimensions();
+        let mip_level_count = 32 - width.max(height).leading_zeros();
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: Some("vulkanR Overlay"),
+            size: Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for mip_level in 0..mip_level_count {
+            let level = if mip_level == 0 {
+                Cow::Borrowed(top.as_ref())
+            } else {
+                let (w, h) = ((linear.width() / 2).max(1), (linear.height() / 2).max(1));
+                linear = imageops::resize(&linear, w, h, FilterType::Triangle);
+                Cow::Owned(srgb8_image(&linear))
+            };
+            self.queue.write_texture(
+                ImageCopyTexture { texture: &texture, mip_level, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+                level.as_raw(),
+                ImageDataLayout { offset: 0, bytes_per_row: Some(4 * level.width()), rows_per_image: Some(level.height()) },
+                Extent3d { width: level.width(), height: level.height(), depth_or_array_layers: 1 },
+            );
+        }
+        texture.create_view(&TextureViewDescriptor::default())
+    }
+
+    /// sRGB color image, supersampled when requested, plus the G-buffer when
+    /// `gbuffer` is set. The G-buffer is always at output resolution.
+    fn color_rgba8(&mut self, scene: &Scene, opts: &RenderOptions, gbuffer: bool) -> Result<(RgbaImage, Option<GBuffer>), VulkanRError> {
+        if opts.supersample > 1 {
+            let img = srgb8_image(&self.render_supersampled(scene, opts)?);
+            let gbuffer = if gbuffer { Some(self.render_gbuffer(scene, opts)?) } else { None };
+            return Ok((img, gbuffer));
+        }
+        let frame = self.render_tiled(scene, opts, TextureFormat::Rgba8UnormSrgb, gbuffer)?;
+        Ok((rgba8_image(opts.width, opts.height, frame.color)?, frame.gbuffer))
+    }
+
+    /// Linear color image counterpart of [`Self::color_rgba8`].
+    fn color_linear(&mut self, scene: &Scene, opts: &RenderOptions, gbuffer: bool) -> Result<(Rgba32FImage, Option<GBuffer>), VulkanRError> {
+        if opts.supersample > 1 {
+            let img = self.render_supersampled(scene, opts)?;
+            let gbuffer = if gbuffer { Some(self.render_gbuffer(scene, opts)?) } else { None };
+            return Ok((img, gbuffer));
+        }
+        let frame = self.render_tiled(scene, opts, TextureFormat::Rgba16Float, gbuffer)?;
+        Ok((linear_image(opts.width, opts.height, frame.color)?, frame.gbuffer))
+    }
+
+    /// Single-sample G-buffer at output resolution.
+    fn render_gbuffer(&mut self, scene: &Scene, opts: &RenderOptions) -> Result<GBuffer, VulkanRError> {
+        let single = RenderOptions { msaa: 1, ..opts.clone() };
+        self.render_tiled(scene, &single, TextureFormat::Rgba8UnormSrgb, true)?
+            .gbuffer
+            .ok_or_else(|| VulkanRError::Capability("G-buffer was not rendered".into()))
+    }
+
+    /// Render at `supersample` times the output resolution and filter down in
+    /// linear space. The large image is rendered in tiles that fit the
+    /// device's texture and buffer limits, each padded by the filter radius
+    /// so tile seams are invisible.
+    fn render_supersampled(&mut self, scene: &Scene, opts: &RenderOptions) -> Result<Rgba32FImage, VulkanRError> {
+        let k = opts.supersample;
+        if !(1..=16).contains(&k) {
+            return Err(VulkanRError::InvalidInput {
+                param: "supersample",
+                reason: format!("must be in 1..=16, got {k}"),
+            });
+        }
+        let margin = opts.filter.margin();
+        let max_dim = self.max_target_dimension(TextureFormat::Rgba16Float);
+        let tile = (max_dim / k).saturating_sub(2 * margin);
+        if tile == 0 {
+            return Err(VulkanRError::Capability(format!(
+                "{k}x supersampling needs render targets larger than this adapter's limit of {max_dim} pixels"
+            )));
+        }
+
+        // Keeping the full size within i32 also keeps the tile offsets and
+        // sizes below in range
+        let (width, height) = (opts.width, opts.height);
+        let scaled = |n: u32| {
+            n.checked_mul(k).filter(|&n| i32::try_from(n).is_ok()).ok_or_else(|| VulkanRError::InvalidInput {
+                param: "supersample",
+                reason: format!("{k}x supersampling of a {width}x{height} image exceeds the largest render size"),
+            })
+        };
+        let hi = RenderOptions { width: scaled(width)?, height: scaled(height)?, ..opts.clone() };
+        let pixels = alloc_image(width as usize * height as usize * 4, "output image")?;
+        let mut out = Rgba32FImage::from_raw(width, height, pixels)
+            .ok_or_else(|| VulkanRError::Io("Output size does not match image dimensions".into()))?;
+        for ty in (0..height).step_by(tile as usize) {
+            for tx in (0..width).step_by(tile as usize) {
+                let (tw, th) = (tile.min(width - tx), tile.min(height - ty));
+                let viewport = Viewport {
+                    full_width: hi.width,
+                    full_height: hi.height,
+                    x: (tx as i32 - margin as i32) * k as i32,
+                    y: (ty as i32 - margin as i32) * k as i32,
+                    width: (tw + 2 * margin) * k,
+                    height: (th + 2 * margin) * k,
+                };
+                let frame = self.render_frame(scene, &hi, viewport, TextureFormat::Rgba16Float, false)?;
+                let texels: Vec<f32> = f16_texels(&frame.color).collect();
+                let (pixels, _, _) = resample::downsample(
+                    &texels,
+                    4,
+                    viewport.width as usize,
+                    viewport.height as usize,
+                    k as usize,
+                    opts.filter,
+                );
+                for (i, p) in pixels.chunks_exact(4).enumerate() {
+                    let (x, y) = (i as u32 % tw, i as u32 / tw);
+                    out.put_pixel(tx + x, ty + y, Rgba([p[0], p[1], p[2], p[3]]));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Render the full `opts.width x opts.height` image, split into tiles
+    /// with cropped projections when it exceeds the device's render target
+    /// limits. Tiles are stitched on the host, so output size is bounded only
+    /// by host memory.
+    fn render_tiled(
+        &mut self,
+        scene: &Scene,
+        opts: &RenderOptions,
+        color_format: TextureFormat,
+        gbuffer: bool,
+    ) -> Result<Frame, VulkanRError> {
+        let mut tile = self.max_target_dimension(color_format);
+        if gbuffer {
+            tile = tile.min(self.max_target_dimension(POSITION_FORMAT));
+        }
+        let (width, height) = (opts.width, opts.height);
+        if width <= tile && height <= tile {
+            return self.render_frame(scene, opts, Viewport::full(opts), color_format, gbuffer);
+        }
+
+        let n = width as usize * height as usize;
+        let bytes_per_pixel = color_format.block_copy_size(None).unwrap_or(4) as usize;
+        let mut color = alloc_image(n * bytes_per_pixel, "output image")?;
+        let mut stitched = if gbuffer {
+            Some(GBuffer {
+                width,
+                height,
+                depth: alloc_image(n, "depth buffer")?,
+                normals: alloc_image(3 * n, "normal buffer")?,
+                positions: alloc_image(3 * n, "position buffer")?,
+                triangles: scene.triangles(),
+            })
+        } else {
+            None
+        };
+
+        for ty in (0..height).step_by(tile as usize) {
+            for tx in (0..width).step_by(tile as usize) {
+                let viewport = Viewport {
+                    full_width: width,
+                    full_height: height,
+                    x: tx as i32,
+                    y: ty as i32,
+                    width: tile.min(width - tx),
+                    height: tile.min(height - ty),
+                };
+                let frame = self.render_frame(scene, opts, viewport, color_format, gbuffer)?;
+                blit(&mut color, width, &frame.color, viewport, bytes_per_pixel);
+                if let (Some(out), Some(gb)) = (stitched.as_mut(), frame.gbuffer) {
+                    blit(&mut out.depth, width, &gb.depth, viewport, 1);
+                    blit(&mut out.normals, width, &gb.normals, viewport, 3);
+                    blit(&mut out.positions, width, &gb.positions, viewport, 3);
+                }
+            }
+        }
+        Ok(Frame { color, gbuffer: stitched })
+    }
+
+    /// Largest square render target of `format` whose readback still fits in
+    /// a single buffer.
+    fn max_target_dimension(&self, format: TextureFormat) -> u32 {
+        let limits = self.device.limits();
+        let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4) as u64;
+        let by_buffer = ((limits.max_buffer_size / bytes_per_pixel) as f64).sqrt() as u32;
+        // Keep rows a multiple of the copy alignment
+        limits.max_texture_dimension_2d.min(by_buffer / 64 * 64)
+    }
+
+    /// Render into a color target of `color_format` and read back its
+    /// texels, plus the G-buffer when `gbuffer` is set.
+    /// `viewport` selects the part of the `opts.width x opts.height` image
+    /// to render; the targets are sized to the viewport.
+    fn render_frame(
+        &mut self,
+        scene: &Scene,
+        opts: &RenderOptions,
+        viewport: Viewport,
+        color_format: TextureFormat,
+        gbuffer: bool,
+    ) -> Result<Frame, VulkanRError> {
+        let RenderOptions { camera, msaa, .. } = *opts;
+        let Viewport { width, height, .. } = viewport;
+        self.check_sample_count(color_format, msaa)?;
+
+        // Depth and G-buffer targets can't be resolved, so read them from a
+        // separate single-sample pass
+        if gbuffer && msaa > 1 {
+            let color = self.render_frame(scene, opts, viewport, color_format, false)?.color;
+            let single = RenderOptions { msaa: 1, ..opts.clone() };
+            let gbuffer = self.render_frame(scene, &single, viewport, color_format, true)?.gbuffer;
+            return Ok(Frame { color, gbuffer });
+        }
+
+        // Render target textures; with MSAA the multisampled color target
+        // resolves into `color_tex`
         let color_tex = self.device.create_texture(&TextureDescriptor {
             label: Some("vulkanR Color"),
             size: Extent3d { width, height, depth_or_array_layers: 1 },
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8UnormSrgb,
+            format: color_format,
             usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
             view_formats: &[],
         });
+        let msaa_tex = (msaa > 1).then(|| {
+            self.device.create_texture(&TextureDescriptor {
+                label: Some("vulkanR Color MSAA"),
+                size: Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: msaa,
+                dimension: TextureDimension::D2,
+                format: color_format,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+        });
         let depth_tex = self.device.create_texture(&TextureDescriptor {
             label: Some("vulkanR Depth"),
             size: Extent3d { width, height, depth_or_array_layers: 1 },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: msaa,
             dimension: TextureDimension::D2,
             format: TextureFormat::Depth32Float,
-            usage: TextureUsages::RENDER_ATTACHMENT,
+            usage: if msaa > 1 {
+                TextureUsages::RENDER_ATTACHMENT
+            } else {
+                TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC
+            },
             view_formats: &[],
         });
         let color_view = color_tex.create_view(&TextureViewDescriptor::default());
+        let msaa_view = msaa_tex.as_ref().map(|t| t.create_view(&TextureViewDescriptor::default()));
         let depth_view = depth_tex.create_view(&TextureViewDescriptor::default());
 
-        // Buffers
-        let vertex_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vulkanR Vertex Buffer"),
-            contents: bytemuck::cast_slice(&mesh.vertices),
-            usage: BufferUsages::VERTEX,
-        });
-        let index_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vulkanR Index Buffer"),
-            contents: bytemuck::cast_slice(&mesh.indices),
-            usage: BufferUsages::INDEX,
-        });
-
-        // Camera & uniforms
-        let aspect = width as f32 / height as f32;
-        let fov_rad = fov_deg.to_radians();
-
-        // Compute a robust camera distance. If scene extents are not readily available here,
-        // use a conservative fallback so tests can compile and render predictably.
-        // TODO(milos): replace the fallback with an AABB-based computation:
-        // let r = radius / (0.5 * fov_rad).tan() + radius;
-        let r: f32 = 3.0;
-        let eye = Vec3::new(r, r, r);
-        let target = Vec3::new(0.0, 0.0, 0.0);
-        let up = Vec3::new(0.0, 1.0, 0.0);
-
-        let view = Mat4::look_at_rh(eye, target, up);
-        let proj = Mat4::perspective_rh(fov_rad, aspect, 0.1, 100.0);
-        let mvp = proj * view;
+        // G-buffer targets: world-space normal and position
+        let aux_tex: Vec<Texture> = if gbuffer {
+            [("vulkanR Normal", NORMAL_FORMAT), ("vulkanR Position", POSITION_FORMAT)]
+                .into_iter()
+                .map(|(label, format)| {
+                    self.device.create_texture(&TextureDescriptor {
+                        label: Some(label),
+                        size: Extent3d { width, height, depth_or_array_layers: 1 },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: TextureDimension::D2,
+                        format,
+                        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                        view_formats: &[],
+                    })
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let aux_views: Vec<TextureView> = aux_tex.iter().map(|t| t.create_view(&TextureViewDescriptor::default())).collect();
 
-        let sun = Vec3::from(sun_dir).normalize();
+        // Camera & uniforms; the camera frames the full image and the
+        // projection is cropped to the viewport
+        let aspect = opts.width as f32 / opts.height as f32;
+        let resolved = camera.resolve(scene.bounds, aspect)?;
+        let mvp = viewport.crop() * resolved.view_proj();
 
-        // Pack uniforms as 20 floats: mvp (16) + sun_dir (3) + padding (1)
-        let mut uniforms: [f32; 20] = [0.0; 20];
-        uniforms[..16].copy_from_slice(&mvp.to_cols_array());
-        uniforms[16..19].copy_from_slice(&sun.to_array());
-        uniforms[19] = 0.0;
+        let uniforms = pack_uniforms(mvp, opts, scene, &scene.shadow);
 
         let uniform_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("vulkanR Uniform Buffer"),
@@ -144,88 +1412,28 @@ impl WgpuRenderer {
             usage: BufferUsages::UNIFORM,
         });
 
-        // Bindings
-        let bgl = self.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("vulkanR BGL"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-        });
+        let key = PipelineKey { geometry: scene.kind(), color_format, gbuffer, sample_count: msaa };
+        self.ensure_pipeline(key);
+        let hp = &self.pipelines[&key];
+
+        let mut entries = vec![
+            BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+            BindGroupEntry { binding: 1, resource: BindingResource::TextureView(&scene.overlay_view) },
+            BindGroupEntry { binding: 2, resource: BindingResource::Sampler(&self.sampler) },
+            BindGroupEntry { binding: 3, resource: BindingResource::TextureView(&scene.shadow.view) },
+            BindGroupEntry { binding: 4, resource: BindingResource::Sampler(&self.shadow_sampler) },
+        ];
+        if let SceneGeometry::Displaced(grid) = &scene.geometry {
+            entries.extend([
+                BindGroupEntry { binding: 5, resource: BindingResource::TextureView(&grid.heights) },
+                BindGroupEntry { binding: 6, resource: BindingResource::TextureView(&grid.ramp) },
+                BindGroupEntry { binding: 7, resource: BindingResource::TextureView(&grid.ambient) },
+            ]);
+        }
         let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
             label: Some("vulkanR Bind Group"),
-            layout: &bgl,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            }],
-        });
-
-        // Shaders
-        let vs = self.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("vulkanR VS"),
-            source: ShaderSource::Wgsl(VERTEX_SHADER.into()),
-        });
-        let fs = self.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("vulkanR FS"),
-            source: ShaderSource::Wgsl(FRAGMENT_SHADER.into()),
-        });
-
-        // Pipeline
-        let layout = self.device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("vulkanR Pipeline Layout"),
-            bind_group_layouts: &[&bgl],
-            push_constant_ranges: &[],
-        });
-        let pipeline = self.device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("vulkanR Pipeline"),
-            layout: Some(&layout),
-            vertex: VertexState {
-                module: &vs,
-                entry_point: "vs_main",
-                buffers: &[VertexBufferLayout {
-                    array_stride: std::mem::size_of::<[f32; 9]>() as BufferAddress,
-                    step_mode: VertexStepMode::Vertex,
-                    attributes: &[ 
-                        VertexAttribute { offset: 0, shader_location: 0, format: VertexFormat::Float32x3 },
-                        VertexAttribute { offset: std::mem::size_of::<[f32; 3]>() as BufferAddress, shader_location: 1, format: VertexFormat::Float32x3 },
-                        VertexAttribute { offset: std::mem::size_of::<[f32; 6]>() as BufferAddress, shader_location: 2, format: VertexFormat::Float32x3 },
-                    ],
-                }],
-            },
-            fragment: Some(FragmentState {
-                module: &fs,
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    format: TextureFormat::Rgba8UnormSrgb,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: Some(DepthStencilState {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState { count: 1, mask: !0, alpha_to_coverage_enabled: false },
-            multiview: None,
+            layout: &hp.bind_group_layout,
+            entries: &entries,
         });
 
         // Encode render pass
@@ -234,16 +1442,25 @@ impl WgpuRenderer {
         });
 
         {
+            let mut color_attachments = vec![Some(RenderPassColorAttachment {
+                view: msaa_view.as_ref().unwrap_or(&color_view),
+                resolve_target: msaa_view.as_ref().map(|_| &color_view),
+                ops: Operations {
+                    load: LoadOp::Clear(Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 }),
+                    store: StoreOp::Store,
+                },
+            })];
+            color_attachments.extend(aux_views.iter().map(|view| {
+                Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations { load: LoadOp::Clear(Color::TRANSPARENT), store: StoreOp::Store },
+                })
+            }));
+
             let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("vulkanR Pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &color_view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 }),
-                        store: StoreOp::Store,
-                    },
-                })],
+                color_attachments: &color_attachments,
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                     view: &depth_view,
                     depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: StoreOp::Store }),
@@ -253,74 +1470,492 @@ impl WgpuRenderer {
                 timestamp_writes: None,
             });
 
-            pass.set_pipeline(&pipeline);
+            pass.set_pipeline(&hp.pipeline);
             pass.set_bind_group(0, &bind_group, &[]);
-            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-            pass.set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
-            pass.draw_indexed(0..mesh.indices.len() as u32, 0, 0..1);
+            scene.draw(&mut pass);
         }
 
-        // Copy to readback buffer
-        let bytes_per_pixel = 4u32;
-        let unpadded = width * bytes_per_pixel;
-        let align = 256u32;
-        let padded = ((unpadded + align - 1) / align) * align;
+        if !gbuffer {
+            let color = self.read_textures(encoder, &[&color_tex])?.remove(0);
+            return Ok(Frame { color, gbuffer: None });
+        }
 
-        let readback = self.device.create_buffer(&BufferDescriptor {
-            label: Some("vulkanR Readback"),
-            size: (padded * height) as u64,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+        let mut texels = self.read_textures(encoder, &[&color_tex, &depth_tex, &aux_tex[0], &aux_tex[1]])?;
+        let (color, depth, normal, position) = {
+            let mut it = texels.drain(..);
+            (it.next().unwrap(), it.next().unwrap(), it.next().unwrap(), it.next().unwrap())
+        };
 
-        encoder.copy_texture_to_buffer(
-            ImageCopyTexture {
-                texture: &color_tex,
-                mip_level: 0,
-                origin: Origin3d::ZERO,
-                aspect: TextureAspect::All,
-            },
-            ImageCopyBuffer {
-                buffer: &readback,
-                layout: ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(padded),
-                    rows_per_image: Some(height),
+        // Pixels left at the cleared depth of 1.0 show background
+        let raw_depth: Vec<f32> = f32_texels(&depth).collect();
+        let hit: Vec<bool> = raw_depth.iter().map(|&d| d < 1.0).collect();
+        let depth: Vec<f32> = raw_depth
+            .iter()
+            .map(|&d| if d < 1.0 { resolved.linear_depth(d) } else { f32::NAN })
+            .collect();
+        let normals: Vec<f32> = normal
+            .chunks_exact(8)
+            .zip(&hit)
+            .flat_map(|(t, &hit)| {
+                (0..3).map(move |c| if hit { f16_to_f32(u16::from_le_bytes([t[2 * c], t[2 * c + 1]])) } else { f32::NAN })
+            })
+            .collect();
+        let positions: Vec<f32> = f32_texels(&position)
+            .collect::<Vec<f32>>()
+            .chunks_exact(4)
+            .zip(&hit)
+            .flat_map(|(t, &hit)| if hit { [t[0], t[1], t[2]] } else { [f32::NAN; 3] })
+            .collect();
+
+        Ok(Frame {
+            color,
+            gbuffer: Some(GBuffer { width, height, depth, normals, positions, triangles: scene.triangles() }),
+        })
+    }
+
+    /// Copy single-sample textures into mapped buffers, submit `encoder`
+    /// and return the texels of each with the 256-byte row padding removed.
+    fn read_textures(&self, mut encoder: CommandEncoder, textures: &[&Texture]) -> Result<Vec<Vec<u8>>, VulkanRError> {
+        let align = COPY_BYTES_PER_ROW_ALIGNMENT;
+        let mut readbacks = Vec::with_capacity(textures.len());
+        for texture in textures {
+            let (width, height) = (texture.width(), texture.height());
+            let aspect = if texture.format().is_depth_stencil_format() {
+                TextureAspect::DepthOnly
+            } else {
+                TextureAspect::All
+            };
+            let bytes_per_pixel = texture.format().block_copy_size(Some(aspect)).unwrap_or(4);
+            let unpadded = width * bytes_per_pixel;
+            let padded = unpadded.div_ceil(align) * align;
+
+            let readback = self.device.create_buffer(&BufferDescriptor {
+                label: Some("vulkanR Readback"),
+                size: padded as u64 * height as u64,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+
+            encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect,
                 },
-            },
-            Extent3d { width, height, depth_or_array_layers: 1 },
-        );
+                ImageCopyBuffer {
+                    buffer: &readback,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded),
+                        rows_per_image: Some(height),
+                    },
+                },
+                Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+            readbacks.push((readback, unpadded, padded, height));
+        }
 
         self.queue.submit(std::iter::once(encoder.finish()));
 
-        // Map and read
-        let slice = readback.slice(..);
-        let (tx, rx) = std::sync::mpsc::channel();
-        slice.map_async(MapMode::Read, move |r| { tx.send(r).unwrap(); });
-        self.device.poll(Maintain::Wait);
-        rx.recv()
-            .map_err(|e| VulkanRError::DeviceInit(format!("Failed to receive from channel: {}", e)))?
-            .map_err(|e| VulkanRError::DeviceInit(format!("Failed to map buffer: {}", e)))?;
-        let data = slice.get_mapped_range();
-
-        // Compose PNG (remove row padding)
-        let mut img = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
-        for y in 0..height {
-            let row = &data[(y * padded) as usize .. (y * padded + unpadded) as usize];
-            for x in 0..width {
-                let o = (x * 4) as usize;
-                let p = Rgba([row[o], row[o + 1], row[o + 2], row[o + 3]]);
-                img.put_pixel(x, y, p);
+        let mut out = Vec::with_capacity(readbacks.len());
+        for (readback, unpadded, padded, height) in readbacks {
+            // Map and read
+            let slice = readback.slice(..);
+            let (tx, rx) = std::sync::mpsc::channel();
+            slice.map_async(MapMode::Read, move |r| { tx.send(r).unwrap(); });
+            self.device.poll(Maintain::Wait);
+            rx.recv()
+                .map_err(|e| VulkanRError::DeviceInit(format!("Failed to receive from channel: {}", e)))?
+                .map_err(|e| VulkanRError::DeviceInit(format!("Failed to map buffer: {}", e)))?;
+            let data = slice.get_mapped_range();
+
+            // Remove row padding
+            let mut texels = Vec::with_capacity(unpadded as usize * height as usize);
+            for y in 0..height as usize {
+                let row = y * padded as usize;
+                texels.extend_from_slice(&data[row..row + unpadded as usize]);
             }
+            drop(data);
+            readback.unmap();
+            out.push(texels);
         }
-        drop(data);
-        readback.unmap();
 
-        // Save
-        img.save(Path::new(output_path))
-            .map_err(|e| VulkanRError::Io(format!("Failed to save image to {}: {}", output_path, e)))?;
+        Ok(out)
+    }
 
+    /// Sample counts usable for both `color_format` and the depth target.
+    pub fn supported_sample_counts(&self, color_format: TextureFormat) -> Vec<u32> {
+        let adapter_specific = self.device.features().contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&n| {
+                if !adapter_specific {
+                    // WebGPU guarantees 1 and 4 for renderable formats
+                    return n == 1 || n == 4;
+                }
+                [color_format, TextureFormat::Depth32Float]
+                    .iter()
+                    .all(|&f| self.adapter.get_texture_format_features(f).flags.sample_count_supported(n))
+            })
+            .collect()
+    }
+
+    fn check_sample_count(&self, color_format: TextureFormat, msaa: u32) -> Result<(), VulkanRError> {
+        if ![1, 2, 4, 8].contains(&msaa) {
+            return Err(VulkanRError::InvalidInput {
+                param: "msaa",
+                reason: format!("must be 1, 2, 4 or 8, got {msaa}"),
+            });
+        }
+        let supported = self.supported_sample_counts(color_format);
+        if !supported.contains(&msaa) {
+            return Err(VulkanRError::Capability(format!(
+                "{msaa}x MSAA is not supported for {color_format:?} on this adapter (supported: {supported:?})"
+            )));
+        }
         Ok(())
     }
+
+    /// Depth-only pipeline for the sun pass, built on first use.
+    fn ensure_shadow_pipeline(&mut self, geometry: Geometry) {
+        let device = &self.device;
+        let vs = &self.vs;
+        self.shadow_pipelines.entry(geometry).or_insert_with(|| {
+            let mut entries = vec![BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }];
+            if geometry == Geometry::Displacement {
+                entries.push(grid_texture_entry(5));
+            }
+            let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("vulkanR Shadow BGL"),
+                entries: &entries,
+            });
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("vulkanR Shadow Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("vulkanR Shadow Pipeline"),
+                layout: Some(&layout),
+                vertex: match geometry {
+                    Geometry::Mesh => VertexState {
+                        module: vs,
+                        entry_point: "vs_shadow",
+                        buffers: &[VertexBufferLayout {
+                            array_stride: (VERTEX_FLOATS * std::mem::size_of::<f32>()) as BufferAddress,
+                            step_mode: VertexStepMode::Vertex,
+                            attributes: &[VertexAttribute { offset: 0, shader_location: 0, format: VertexFormat::Float32x3 }],
+                        }],
+                    },
+                    Geometry::Displacement => VertexState { module: vs, entry_point: "vs_shadow_displaced", buffers: &[] },
+                },
+                fragment: None,
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    // Both faces cast shadows; the terrain is an open surface
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    // Slope-scaled bias against shadow acne on steep faces
+                    bias: DepthBiasState { constant: 2, slope_scale: 2.0, clamp: 0.0 },
+                }),
+                multisample: MultisampleState::default(),
+                multiview: None,
+            });
+            HeightmapPipeline { bind_group_layout, pipeline }
+        });
+    }
+
+    /// Build the heightmap pipeline for `key` unless it is already cached.
+    fn ensure_pipeline(&mut self, key: PipelineKey) {
+        let device = &self.device;
+        let (vs, fs) = (&self.vs, &self.fs);
+        self.pipelines.entry(key).or_insert_with(|| {
+            let mut entries = vec![
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ];
+            if key.geometry == Geometry::Displacement {
+                entries.extend([5, 6, 7].map(grid_texture_entry));
+            }
+            let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("vulkanR BGL"),
+                entries: &entries,
+            });
+            let mut targets = vec![Some(ColorTargetState {
+                format: key.color_format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            })];
+            if key.gbuffer {
+                targets.extend([NORMAL_FORMAT, POSITION_FORMAT].map(|format| {
+                    Some(ColorTargetState { format, blend: None, write_mask: ColorWrites::ALL })
+                }));
+            }
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("vulkanR Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("vulkanR Pipeline"),
+                layout: Some(&layout),
+                vertex: match key.geometry {
+                    Geometry::Mesh => VertexState {
+                        module: vs,
+                        entry_point: "vs_main",
+                        buffers: &[VertexBufferLayout {
+                            array_stride: (VERTEX_FLOATS * std::mem::size_of::<f32>()) as BufferAddress,
+                            step_mode: VertexStepMode::Vertex,
+                            attributes: &[
+                                VertexAttribute { offset: 0, shader_location: 0, format: VertexFormat::Float32x3 },
+                                VertexAttribute { offset: std::mem::size_of::<[f32; 3]>() as BufferAddress, shader_location: 1, format: VertexFormat::Float32x3 },
+                                VertexAttribute { offset: std::mem::size_of::<[f32; 6]>() as BufferAddress, shader_location: 2, format: VertexFormat::Float32x3 },
+                                VertexAttribute { offset: std::mem::size_of::<[f32; 9]>() as BufferAddress, shader_location: 3, format: VertexFormat::Float32x2 },
+                                VertexAttribute { offset: std::mem::size_of::<[f32; 11]>() as BufferAddress, shader_location: 4, format: VertexFormat::Float32 },
+                            ],
+                        }],
+                    },
+                    // The grid is implicit in the vertex and instance indices
+                    Geometry::Displacement => VertexState { module: vs, entry_point: "vs_displaced", buffers: &[] },
+                },
+                fragment: Some(FragmentState {
+                    module: fs,
+                    entry_point: if key.gbuffer { "fs_gbuffer" } else { "fs_main" },
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    cull_mode: Some(Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: MultisampleState { count: key.sample_count, mask: !0, alpha_to_coverage_enabled: false },
+                multiview: None,
+            });
+            HeightmapPipeline { bind_group_layout, pipeline }
+        });
+    }
 }
 
+/// Vertex-stage texture read with `textureLoad` by displaced grids.
+fn grid_texture_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// Pack the shader uniforms as 48 floats: mvp (16), light_vp (16), sun_dir
+/// (3), overlay opacity, shadow intensity, shadow texel size, padding (2)
+/// and, for displaced grids, the grid size and spacing (4) and height scale,
+/// color domain and patch size (4).
+fn pack_uniforms(mvp: Mat4, opts: &RenderOptions, scene: &Scene, shadow: &ShadowMap) -> [f32; 48] {
+    let sun = Vec3::from(opts.sun_dir).normalize();
+    let mut uniforms = [0.0f32; 48];
+    uniforms[..16].copy_from_slice(&mvp.to_cols_array());
+    uniforms[16..32].copy_from_slice(&shadow.light_vp.to_cols_array());
+    uniforms[32..35].copy_from_slice(&sun.to_array());
+    uniforms[35] = scene.overlay_opacity;
+    uniforms[36] = shadow.intensity;
+    uniforms[37] = 1.0 / shadow.size as f32;
+    if let SceneGeometry::Displaced(grid) = &scene.geometry {
+        let (sx, sy, sz) = grid.scale;
+        uniforms[40..44].copy_from_slice(&[grid.cols as f32, grid.rows as f32, sx, sy]);
+        uniforms[44..48].copy_from_slice(&[sz, grid.domain.0, grid.domain.1, PATCH_CELLS as f32]);
+    }
+    uniforms
+}
+
+/// Per-cell ambient factor from the sky-view factor of the displayed
+/// geometry, or `None` when ambient occlusion is off.
+fn ambient_occlusion(z_data: &[f32], rows: usize, cols: usize, opts: &RenderOptions) -> Result<Option<Vec<f32>>, VulkanRError> {
+    let Some(ao) = opts.ambient_occlusion else {
+        return Ok(None);
+    };
+    if !(0.0..=1.0).contains(&ao.strength) {
+        return Err(VulkanRError::InvalidInput {
+            param: "ao",
+            reason: format!("strength must be in [0, 1], got {}", ao.strength),
+        });
+    }
+    // Occlude against the displayed geometry, cell sizes in height units
+    let (sx, sy, sz) = opts.spacing.mesh_scale(rows, cols, opts.scale_z)?;
+    let sz = sz.max(f32::MIN_POSITIVE);
+    let grid = Grid::new(z_data, rows, cols, sx / sz, sy / sz)?;
+    let svf = analysis::sky_view_factor(&grid, ao.directions as usize, ao.radius as usize)?;
+    Ok(Some(svf.iter().map(|&v| 1.0 - ao.strength * (1.0 - v)).collect()))
+}
+
+/// Overlay image and opacity for a grid: `opts.overlay` with the
+/// `opts.viewshed` tint composited over it, or the tint alone at one texel
+/// per cell.
+fn draped_overlay(opts: &RenderOptions, rows: usize, cols: usize) -> Result<Option<(Cow<'_, RgbaImage>, f32)>, VulkanRError> {
+    let Some(tint) = &opts.viewshed else {
+        return Ok(opts.overlay.as_ref().map(|o| (Cow::Borrowed(o.image.as_ref()), o.opacity)));
+    };
+    if tint.cells.len() != rows * cols {
+        return Err(VulkanRError::InvalidInput {
+            param: "viewshed",
+            reason: format!("expected {} cells for the {rows}x{cols} grid, got {}", rows * cols, tint.cells.len()),
+        });
+    }
+    let (mut image, opacity) = match &opts.overlay {
+        Some(o) => (o.image.as_ref().clone(), o.opacity),
+        // Transparent texels in the tint color keep filtered edges from darkening
+        None => (RgbaImage::from_pixel(cols as u32, rows as u32, Rgba([tint.color[0], tint.color[1], tint.color[2], 0])), 0.0),
+    };
+    // The overlay opacity goes into its alpha so it doesn't fade the tint
+    let alpha = tint.color[3] as f32 / 255.0;
+    let (width, height) = image.dimensions();
+    // Texture coordinates run from the first to the last cell centre
+    let cell = |texel: u32, size: u32, cells: usize| ((texel as f32 + 0.5) / size as f32 * (cells - 1) as f32).round() as usize;
+    for (x, y, px) in image.enumerate_pixels_mut() {
+        let under = px[3] as f32 / 255.0 * opacity;
+        if !tint.cells[cell(y, height, rows) * cols + cell(x, width, cols)] {
+            px[3] = (under * 255.0).round() as u8;
+            continue;
+        }
+        let out = alpha + under * (1.0 - alpha);
+        for c in 0..3 {
+            let mixed = tint.color[c] as f32 * alpha + px[c] as f32 * under * (1.0 - alpha);
+            px[c] = (mixed / out.max(f32::MIN_POSITIVE)).round() as u8;
+        }
+        px[3] = (out * 255.0).round() as u8;
+    }
+    Ok(Some((Cow::Owned(image), 1.0)))
+}
+
+fn rgba8_image(width: u32, height: u32, texels: Vec<u8>) -> Result<RgbaImage, VulkanRError> {
+    RgbaImage::from_raw(width, height, texels)
+        .ok_or_else(|| VulkanRError::Io("Readback size does not match image dimensions".into()))
+}
+
+fn linear_image(width: u32, height: u32, texels: Vec<u8>) -> Result<Rgba32FImage, VulkanRError> {
+    Rgba32FImage::from_raw(width, height, f16_texels(&texels).collect())
+        .ok_or_else(|| VulkanRError::Io("Readback size does not match image dimensions".into()))
+}
+
+/// Decode 8-bit sRGB to linear floats; alpha stays linear.
+fn linear_rgba(img: &RgbaImage) -> Rgba32FImage {
+    Rgba32FImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y);
+        let c = |v: u8| srgb_to_linear(v as f32 / 255.0);
+        Rgba([c(p[0]), c(p[1]), c(p[2]), p[3] as f32 / 255.0])
+    })
+}
+
+/// Encode a linear float image as 8-bit sRGB, matching an `Rgba8UnormSrgb`
+/// render target.
+fn srgb8_image(img: &Rgba32FImage) -> RgbaImage {
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y);
+        let q = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Rgba([q(linear_to_srgb(p[0])), q(linear_to_srgb(p[1])), q(linear_to_srgb(p[2])), q(p[3])])
+    })
+}
+
+/// Allocate a zeroed host buffer for a full-size image, reporting a
+/// capability error instead of aborting when memory can't hold it.
+fn alloc_image<T: Copy + Default>(len: usize, what: &str) -> Result<Vec<T>, VulkanRError> {
+    let mut v = Vec::new();
+    v.try_reserve_exact(len).map_err(|_| {
+        VulkanRError::Capability(format!(
+            "not enough memory for the {what} ({} MiB)",
+            (len * std::mem::size_of::<T>()) >> 20
+        ))
+    })?;
+    v.resize(len, T::default());
+    Ok(v)
+}
+
+/// Copy a tile with `channels` values per pixel into a row-major image
+/// `dst_width` pixels wide at the tile's viewport position.
+fn blit<T: Copy>(dst: &mut [T], dst_width: u32, src: &[T], tile: Viewport, channels: usize) {
+    let row = tile.width as usize * channels;
+    for (y, src_row) in src.chunks_exact(row).enumerate() {
+        let start = ((tile.y as usize + y) * dst_width as usize + tile.x as usize) * channels;
+        dst[start..start + row].copy_from_slice(src_row);
+    }
+}
+
+/// Decode little-endian half-float texels.
+fn f16_texels(bytes: &[u8]) -> impl Iterator<Item = f32> + '_ {
+    bytes.chunks_exact(2).map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+}
+
+/// Decode little-endian `f32` texels.
+fn f32_texels(bytes: &[u8]) -> impl Iterator<Item = f32> + '_ {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
@@ -0,0 +1,113 @@
+use crate::errors::VulkanRError;
+
+/// Reconstruction filter used to downsample supersampled renders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleFilter {
+    /// Average of each k x k block.
+    Box,
+    /// Windowed sinc with three lobes, scaled to the downsampling factor.
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    pub fn parse(name: &str) -> Result<Self, VulkanRError> {
+        match name {
+            "box" => Ok(Self::Box),
+            "lanczos" | "lanczos3" => Ok(Self::Lanczos3),
+            other => Err(VulkanRError::InvalidInput {
+                param: "filter",
+                reason: format!("unknown filter '{other}'"),
+            }),
+        }
+    }
+
+    /// Filter radius in output pixels, i.e. how many extra output pixels a
+    /// tile needs on each side so its interior matches an untiled render.
+    pub fn margin(&self) -> u32 {
+        match self {
+            Self::Box => 0,
+            Self::Lanczos3 => 3,
+        }
+    }
+
+    /// Normalized weights over source pixels for output pixel `i` when
+    /// downsampling by `k`, as `(first source index, weights)`. Indices are
+    /// clamped to `[0, len)` by the caller.
+    fn weights(&self, i: usize, k: usize) -> (isize, Vec<f32>) {
+        match self {
+            Self::Box => ((i * k) as isize, vec![1.0 / k as f32; k]),
+            Self::Lanczos3 => {
+                let center = (i as f32 + 0.5) * k as f32;
+                let radius = 3.0 * k as f32;
+                let first = (center - radius).floor() as isize;
+                let last = (center + radius).ceil() as isize;
+                let mut w: Vec<f32> = (first..last)
+                    .map(|s| lanczos3((s as f32 + 0.5 - center) / k as f32))
+                    .collect();
+                let sum: f32 = w.iter().sum();
+                w.iter_mut().for_each(|v| *v /= sum);
+                (first, w)
+            }
+        }
+    }
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else if x.abs() >= 3.0 {
+        0.0
+    } else {
+        let px = std::f32::consts::PI * x;
+        3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+    }
+}
+
+/// Downsample an interleaved float image of `src_w x src_h` pixels by the
+/// integer factor `k`. The source is expected to carry `filter.margin()`
+/// output pixels of margin on every side; the returned image is the interior
+/// of `(src_w / k - 2m) x (src_h / k - 2m)` pixels.
+pub fn downsample(
+    src: &[f32],
+    channels: usize,
+    src_w: usize,
+    src_h: usize,
+    k: usize,
+    filter: ResampleFilter,
+) -> (Vec<f32>, usize, usize) {
+    let m = filter.margin() as usize;
+    let out_w = src_w / k - 2 * m;
+    let out_h = src_h / k - 2 * m;
+
+    // Horizontal pass: src_h rows x out_w columns
+    let mut tmp = vec![0.0f32; src_h * out_w * channels];
+    for x in 0..out_w {
+        let (first, w) = filter.weights(x + m, k);
+        for y in 0..src_h {
+            let row = &src[y * src_w * channels..(y + 1) * src_w * channels];
+            let dst = &mut tmp[(y * out_w + x) * channels..(y * out_w + x + 1) * channels];
+            for (t, wt) in w.iter().enumerate() {
+                let sx = (first + t as isize).clamp(0, src_w as isize - 1) as usize;
+                for c in 0..channels {
+                    dst[c] += wt * row[sx * channels + c];
+                }
+            }
+        }
+    }
+
+    // Vertical pass: out_h rows x out_w columns
+    let mut out = vec![0.0f32; out_h * out_w * channels];
+    for y in 0..out_h {
+        let (first, w) = filter.weights(y + m, k);
+        for (t, wt) in w.iter().enumerate() {
+            let sy = (first + t as isize).clamp(0, src_h as isize - 1) as usize;
+            let src_row = &tmp[sy * out_w * channels..(sy + 1) * out_w * channels];
+            let dst_row = &mut out[y * out_w * channels..(y + 1) * out_w * channels];
+            for (d, s) in dst_row.iter_mut().zip(src_row) {
+                *d += wt * s;
+            }
+        }
+    }
+
+    (out, out_w, out_h)
+}
@@ -1,7 +1,15 @@
 pub const VERTEX_SHADER: &str = r#"
 struct Uniforms {
     mvp: mat4x4<f32>,
+    light_vp: mat4x4<f32>,
     sun_dir: vec3<f32>,
+    overlay_alpha: f32,
+    shadow_intensity: f32,
+    shadow_texel: f32,
+    // Displaced grid: columns, rows, scene spacing of columns and of rows
+    grid: vec4<f32>,
+    // Displaced grid: scene units per height, color domain, patch size
+    terrain: vec4<f32>,
 }
 
 @group(0) @binding(0)
@@ -11,6 +19,8 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) color: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+    @location(4) ao: f32,
 }
 
 struct VertexOutput {
@@ -18,6 +28,8 @@ struct VertexOutput {
     @location(0) color: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) world_pos: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+    @location(4) ao: f32,
 }
 
 @vertex
@@ -28,41 +40,312 @@ fn vs_main(input: VertexInput) -> VertexOutput {
     output.color = input.color;
     output.normal = input.normal;
     output.world_pos = input.position;
+    output.uv = input.uv;
+    output.ao = input.ao;
     
     return output;
 }
+
+// Depth-only pass from the sun for the shadow map
+@vertex
+fn vs_shadow(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return uniforms.light_vp * vec4<f32>(position, 1.0);
+}
+
+// Displaced grid: heights (R32Float), linear color lookup table and ambient
+// occlusion (1x1 when off), all read with textureLoad
+@group(0) @binding(5)
+var height_tex: texture_2d<f32>;
+@group(0) @binding(6)
+var ramp_tex: texture_2d<f32>;
+@group(0) @binding(7)
+var ao_tex: texture_2d<f32>;
+
+fn is_finite(x: f32) -> bool {
+    // Test the bits; x == x may be folded away
+    return (bitcast<u32>(x) & 0x7fffffffu) < 0x7f800000u;
+}
+
+fn grid_height(cell: vec2<i32>) -> f32 {
+    return textureLoad(height_tex, cell.yx, 0).r;
+}
+
+// On the grid with a finite height
+fn grid_present(cell: vec2<i32>) -> bool {
+    let size = vec2<i32>(uniforms.grid.yx);
+    return all(cell >= vec2<i32>(0)) && all(cell < size) && is_finite(grid_height(cell));
+}
+
+// Height and 1 for a present cell, zeros otherwise
+fn neighbour(cell: vec2<i32>) -> vec2<f32> {
+    if (!grid_present(cell)) {
+        return vec2<f32>(0.0);
+    }
+    return vec2<f32>(grid_height(cell), 1.0);
+}
+
+// Central difference, one-sided next to missing neighbours, as on the CPU
+fn derivative(before: vec2<f32>, center: f32, after: vec2<f32>, spacing: f32) -> f32 {
+    if (before.y > 0.0 && after.y > 0.0) {
+        return (after.x - before.x) / (2.0 * spacing);
+    }
+    if (before.y > 0.0) {
+        return (center - before.x) / spacing;
+    }
+    if (after.y > 0.0) {
+        return (after.x - center) / spacing;
+    }
+    return 0.0;
+}
+
+struct GridVertex {
+    // Row and column
+    cell: vec2<i32>,
+    // False for vertices of triangles touching a missing height
+    keep: bool,
+}
+
+// Grid point of a vertex of the instanced patches. Each instance covers a
+// square patch of terrain.w cells per side with six vertices per cell, split
+// along the diagonal that avoids a missing corner like the CPU mesh.
+fn grid_vertex(vertex: u32, instance: u32) -> GridVertex {
+    let size = vec2<i32>(uniforms.grid.yx);
+    let side = i32(uniforms.terrain.w);
+    let patches_x = (size.y - 2) / side + 1;
+    let cell = i32(vertex / 6u);
+    let origin = vec2<i32>(i32(instance) / patches_x, i32(instance) % patches_x) * side;
+    let quad = origin + vec2<i32>(cell / side, cell % side);
+
+    var out: GridVertex;
+    out.cell = quad;
+    out.keep = false;
+    if (any(quad >= size - 1)) {
+        return out;
+    }
+    // Corners (i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)
+    var present = array<bool, 4>(
+        grid_present(quad),
+        grid_present(quad + vec2<i32>(1, 0)),
+        grid_present(quad + vec2<i32>(0, 1)),
+        grid_present(quad + vec2<i32>(1, 1)),
+    );
+    var corners = array<i32, 6>(0, 1, 3, 0, 3, 2);
+    if (present[1] && present[2]) {
+        corners = array<i32, 6>(0, 1, 2, 1, 3, 2);
+    }
+    let corner = i32(vertex % 6u);
+    let first = corner / 3 * 3;
+    out.keep = present[corners[first]] && present[corners[first + 1]] && present[corners[first + 2]];
+    let c = corners[corner];
+    out.cell = quad + vec2<i32>(c % 2, c / 2);
+    return out;
+}
+
+// Scene position of a grid point, matching HeightfieldMesh
+fn grid_position(cell: vec2<i32>, z: f32) -> vec3<f32> {
+    let size = uniforms.grid.xy;
+    let x = (f32(cell.y) - (size.x - 1.0) / 2.0) * uniforms.grid.z;
+    let y = (f32(cell.x) - (size.y - 1.0) / 2.0) * uniforms.grid.w;
+    return vec3<f32>(x, z * uniforms.terrain.x, y);
+}
+
+// Linear color from the lookup table for an unscaled height
+fn ramp_color(z: f32) -> vec3<f32> {
+    let domain = uniforms.terrain.yz;
+    var t = 0.5;
+    if (domain.y > domain.x) {
+        t = clamp((z - domain.x) / (domain.y - domain.x), 0.0, 1.0);
+    }
+    let n = i32(textureDimensions(ramp_tex).x);
+    let x = t * f32(n - 1);
+    let k = min(i32(x), n - 2);
+    let lo = textureLoad(ramp_tex, vec2<i32>(k, 0), 0).rgb;
+    let hi = textureLoad(ramp_tex, vec2<i32>(k + 1, 0), 0).rgb;
+    return mix(lo, hi, x - f32(k));
+}
+
+// Dropped triangles collapse onto one point outside the view volume
+const CULLED = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+
+@vertex
+fn vs_displaced(@builtin(vertex_index) vertex: u32, @builtin(instance_index) instance: u32) -> VertexOutput {
+    var output: VertexOutput;
+    let v = grid_vertex(vertex, instance);
+    if (!v.keep) {
+        output.clip_position = CULLED;
+        return output;
+    }
+    let z = grid_height(v.cell);
+    let position = grid_position(v.cell, z);
+
+    let gx = derivative(neighbour(v.cell - vec2<i32>(0, 1)), z, neighbour(v.cell + vec2<i32>(0, 1)), uniforms.grid.z);
+    let gy = derivative(neighbour(v.cell - vec2<i32>(1, 0)), z, neighbour(v.cell + vec2<i32>(1, 0)), uniforms.grid.w);
+    let normal = vec3<f32>(-gx * uniforms.terrain.x, 1.0, -gy * uniforms.terrain.x);
+    let len = length(normal);
+
+    let size = uniforms.grid.xy;
+    let ao_size = vec2<i32>(textureDimensions(ao_tex));
+    output.clip_position = uniforms.mvp * vec4<f32>(position, 1.0);
+    output.color = ramp_color(z);
+    output.normal = select(vec3<f32>(0.0, 1.0, 0.0), normal / len, len > 0.0 && is_finite(len));
+    output.world_pos = position;
+    output.uv = vec2<f32>(f32(v.cell.y) / (size.x - 1.0), f32(v.cell.x) / (size.y - 1.0));
+    output.ao = textureLoad(ao_tex, min(v.cell.yx, ao_size - 1), 0).r;
+    return output;
+}
+
+@vertex
+fn vs_shadow_displaced(@builtin(vertex_index) vertex: u32, @builtin(instance_index) instance: u32) -> @builtin(position) vec4<f32> {
+    let v = grid_vertex(vertex, instance);
+    if (!v.keep) {
+        return CULLED;
+    }
+    return uniforms.light_vp * vec4<f32>(grid_position(v.cell, grid_height(v.cell)), 1.0);
+}
 "#;
 
 pub const FRAGMENT_SHADER: &str = r#"
 struct Uniforms {
     mvp: mat4x4<f32>,
+    light_vp: mat4x4<f32>,
     sun_dir: vec3<f32>,
+    overlay_alpha: f32,
+    shadow_intensity: f32,
+    shadow_texel: f32,
+    // Displaced grid: columns, rows, scene spacing of columns and of rows
+    grid: vec4<f32>,
+    // Displaced grid: scene units per height, color domain, patch size
+    terrain: vec4<f32>,
 }
 
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
+// Draped overlay image; a 1x1 placeholder when none is set
+@group(0) @binding(1)
+var overlay_tex: texture_2d<f32>;
+@group(0) @binding(2)
+var overlay_sampler: sampler;
+
+// Sun depth map; only read when shadow_intensity > 0
+@group(0) @binding(3)
+var shadow_map: texture_depth_2d;
+@group(0) @binding(4)
+var shadow_sampler: sampler_comparison;
+
 struct FragmentInput {
     @location(0) color: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) world_pos: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+    @location(4) ao: f32,
 }
 
-@fragment
-fn fs_main(input: FragmentInput) -> @location(0) vec4<f32> {
+// Fraction of the sun visible from `world_pos`, 3x3 PCF
+fn sun_visibility(world_pos: vec3<f32>) -> f32 {
+    let light = uniforms.light_vp * vec4<f32>(world_pos, 1.0);
+    let ndc = light.xyz / light.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * uniforms.shadow_texel;
+            lit = lit + textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z - 0.001);
+        }
+    }
+    return lit / 9.0;
+}
+
+fn shade(input: FragmentInput) -> vec4<f32> {
     let normal = normalize(input.normal);
+    let overlay = textureSample(overlay_tex, overlay_sampler, input.uv);
+    let base_color = mix(input.color, overlay.rgb, overlay.a * uniforms.overlay_alpha);
     let sun_dir = normalize(uniforms.sun_dir);
     
-    // Ambient lighting
-    let ambient = 0.45;
+    // Ambient lighting, occluded in valleys and gullies
+    let ambient = 0.45 * input.ao;
     
-    // Diffuse lighting
-    let diffuse = max(dot(normal, sun_dir), 0.0) * 0.7;
+    // Diffuse lighting, darkened where the sun is occluded
+    var shadow = 1.0;
+    if (uniforms.shadow_intensity > 0.0) {
+        shadow = 1.0 - uniforms.shadow_intensity * (1.0 - sun_visibility(input.world_pos));
+    }
+    let diffuse = max(dot(normal, sun_dir), 0.0) * 0.7 * shadow;
     
     // Final color
     let lighting = ambient + diffuse;
-    let final_color = input.color * lighting;
+    let final_color = base_color * lighting;
     
     return vec4<f32>(final_color, 1.0);
 }
-"#;
\ No newline at end of file
+
+@fragment
+fn fs_main(input: FragmentInput) -> @location(0) vec4<f32> {
+    return shade(input);
+}
+
+// G-buffer variant: color plus world-space normal and position targets
+struct GBufferOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) position: vec4<f32>,
+}
+
+@fragment
+fn fs_gbuffer(input: FragmentInput) -> GBufferOutput {
+    var output: GBufferOutput;
+    output.color = shade(input);
+    output.normal = vec4<f32>(normalize(input.normal), 1.0);
+    output.position = vec4<f32>(input.world_pos, 1.0);
+    return output;
+}
+"#;
+
+pub const SUN_SHADER: &str = r#"
+struct Sun {
+    light_vp: mat4x4<f32>,
+    // Unit vector towards the sun in scene space, beam energy in w
+    toward: vec4<f32>,
+    // Hours of the step and normal offset in scene units
+    params: vec4<f32>,
+}
+
+// Scene position (w 0 on missing cells) and unit normal of a grid cell
+struct Cell {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> sun: Sun;
+@group(0) @binding(1)
+var<storage, read> cells: array<Cell>;
+// Sun hours and direct energy per cell, summed over the sun positions
+@group(0) @binding(2)
+var<storage, read_write> totals: array<vec2<f32>>;
+@group(0) @binding(3)
+var shadow_map: texture_depth_2d;
+@group(0) @binding(4)
+var shadow_sampler: sampler_comparison;
+
+@compute @workgroup_size(64)
+fn cs_sun(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let k = id.x + id.y * groups.x * 64u;
+    if (k >= arrayLength(&cells)) {
+        return;
+    }
+    let cell = cells[k];
+    let incidence = dot(cell.normal.xyz, sun.toward.xyz);
+    if (cell.position.w == 0.0 || incidence <= 0.0) {
+        return;
+    }
+    // Offset along the normal so the cell doesn't shadow itself
+    let clip = sun.light_vp * vec4<f32>(cell.position.xyz + cell.normal.xyz * sun.params.y, 1.0);
+    let uv = vec2<f32>(clip.x * 0.5 + 0.5, 0.5 - clip.y * 0.5);
+    let lit = textureSampleCompareLevel(shadow_map, shadow_sampler, uv, clip.z);
+    totals[k] = totals[k] + lit * vec2<f32>(sun.params.x, sun.toward.w * incidence);
+}
+"#;
@@ -0,0 +1,161 @@
+use crate::analysis::{map_cells, Grid};
+use crate::errors::VulkanRError;
+
+/// Largest RTIN tile edge in cells. Bigger grids are split into tiles whose
+/// shared edges are kept at full resolution so they join without cracks.
+const MAX_TILE: usize = 1024;
+
+/// Right-triangulated irregular network (RTIN) of a height grid: triangles
+/// are split along their hypotenuse until the linear interpolation of every
+/// grid point they cover is within `max_error` of its height. Triangles
+/// touching a missing (non-finite) height are left out, as in the full mesh.
+///
+/// Returns triangles as row-major grid indices, wound counter-clockwise when
+/// seen from above like [`crate::mesh::HeightfieldMesh::new`].
+pub fn rtin_triangles(grid: &Grid, max_error: f32) -> Result<Vec<[u32; 3]>, VulkanRError> {
+    if !(max_error >= 0.0 && max_error.is_finite()) {
+        return Err(VulkanRError::InvalidInput {
+            param: "max_error",
+            reason: format!("must be a non-negative number, got {max_error}"),
+        });
+    }
+    let tile = (grid.rows.min(grid.cols) - 1).next_power_of_two().clamp(2, MAX_TILE);
+    let (tiles_i, tiles_j) = ((grid.rows - 1).div_ceil(tile), (grid.cols - 1).div_ceil(tile));
+    // Tiles are independent apart from their full-resolution edges
+    let parts = map_cells(tiles_i, tiles_j, |ti, tj| {
+        let mut tris = Vec::new();
+        Tile::new(grid, (ti * tile, tj * tile), tile).triangles(max_error, &mut tris);
+        tris
+    });
+    let triangles: Vec<[u32; 3]> = parts.concat();
+    if triangles.is_empty() {
+        return Err(VulkanRError::InvalidInput {
+            param: "z",
+            reason: "no grid cell has three finite corner heights to triangulate".into(),
+        });
+    }
+    Ok(triangles)
+}
+
+/// One square `(size + 1) x (size + 1)` block of the grid with its error
+/// bounds. Points past the grid edge are missing.
+struct Tile<'a> {
+    grid: &'a Grid<'a>,
+    origin: (usize, usize),
+    size: usize,
+    errors: Vec<f32>,
+}
+
+impl<'a> Tile<'a> {
+    fn new(grid: &'a Grid<'a>, origin: (usize, usize), size: usize) -> Self {
+        let mut tile = Self { grid, origin, size, errors: vec![0.0; (size + 1) * (size + 1)] };
+        tile.compute_errors();
+        tile
+    }
+
+    /// Height at tile coordinates, NaN outside the grid.
+    fn height(&self, x: usize, y: usize) -> f32 {
+        let (i, j) = (self.origin.0 + y, self.origin.1 + x);
+        if i < self.grid.rows && j < self.grid.cols {
+            self.grid.at(i, j)
+        } else {
+            f32::NAN
+        }
+    }
+
+    /// Edges shared with a neighbouring tile, kept at full resolution.
+    fn on_seam(&self, x: usize, y: usize) -> bool {
+        let (i, j) = (self.origin.0 + y, self.origin.1 + x);
+        (x == 0 && j > 0)
+            || (y == 0 && i > 0)
+            || (x == self.size && j < self.grid.cols - 1)
+            || (y == self.size && i < self.grid.rows - 1)
+    }
+
+    /// Bound the interpolation error of every triangle in the hierarchy,
+    /// stored at the midpoint of its hypotenuse. A triangle's error is at most
+    /// the error at that midpoint plus the larger error of its two halves,
+    /// and the value is shared with the neighbour across the hypotenuse so
+    /// both split together and the mesh stays crack-free.
+    fn compute_errors(&mut self) {
+        let n = self.size + 1;
+        let count = self.size * self.size * 2 - 2;
+        let parents = count - self.size * self.size;
+        for t in (0..count).rev() {
+            let [ax, ay, bx, by, cx, cy] = triangle_coords(t, self.size);
+            let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+            let (za, zb, zm) = (self.height(ax, ay), self.height(bx, by), self.height(mx, my));
+            let mut error = if self.on_seam(mx, my) { f32::INFINITY } else { ((za + zb) / 2.0 - zm).abs() };
+            if error.is_nan() {
+                error = f32::INFINITY;
+            }
+            if t < parents {
+                let left = ((ay + cy) / 2) * n + (ax + cx) / 2;
+                let right = ((by + cy) / 2) * n + (bx + cx) / 2;
+                error += self.errors[left].max(self.errors[right]);
+            }
+            let mid = &mut self.errors[my * n + mx];
+            *mid = mid.max(error);
+        }
+    }
+
+    fn triangles(&self, max_error: f32, out: &mut Vec<[u32; 3]>) {
+        let s = self.size;
+        self.split([0, 0], [s, s], [s, 0], max_error, out);
+        self.split([s, s], [0, 0], [0, s], max_error, out);
+    }
+
+    /// Emit triangle `abc` (right angle at `c`) or recurse into its halves.
+    fn split(&self, a: [usize; 2], b: [usize; 2], c: [usize; 2], max_error: f32, out: &mut Vec<[u32; 3]>) {
+        // Nothing to emit past the far grid edges
+        let (i0, j0) = (self.origin.0 + a[1].min(b[1]).min(c[1]), self.origin.1 + a[0].min(b[0]).min(c[0]));
+        if i0 >= self.grid.rows || j0 >= self.grid.cols {
+            return;
+        }
+        let m = [(a[0] + b[0]) / 2, (a[1] + b[1]) / 2];
+        let legs = a[0].abs_diff(c[0]) + a[1].abs_diff(c[1]);
+        if legs > 1 && self.errors[m[1] * (self.size + 1) + m[0]] > max_error {
+            self.split(c, a, m, max_error, out);
+            self.split(b, c, m, max_error, out);
+            return;
+        }
+        if [a, b, c].iter().any(|p| !self.height(p[0], p[1]).is_finite()) {
+            return;
+        }
+        let index = |p: [usize; 2]| ((self.origin.0 + p[1]) * self.grid.cols + self.origin.1 + p[0]) as u32;
+        // Counter-clockwise from above: positive cross product in (row, col)
+        let cross = |p: [usize; 2]| [p[1] as i64, p[0] as i64];
+        let ([ai, aj], [bi, bj], [ci, cj]) = (cross(a), cross(b), cross(c));
+        if (bi - ai) * (cj - aj) - (bj - aj) * (ci - ai) > 0 {
+            out.push([index(a), index(b), index(c)]);
+        } else {
+            out.push([index(a), index(c), index(b)]);
+        }
+    }
+}
+
+/// Corners `[ax, ay, bx, by, cx, cy]` of triangle `t` in the RTIN hierarchy
+/// of a tile, where `c` is the right angle and `ab` the hypotenuse.
+fn triangle_coords(t: usize, size: usize) -> [usize; 6] {
+    let mut id = t + 2;
+    let (mut ax, mut ay, mut bx, mut by, mut cx, mut cy) = (0, 0, 0, 0, 0, 0);
+    if id & 1 == 1 {
+        (bx, by, cx) = (size, size, size);
+    } else {
+        (ax, ay, cy) = (size, size, size);
+    }
+    loop {
+        id >>= 1;
+        if id <= 1 {
+            break;
+        }
+        let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+        if id & 1 == 1 {
+            (bx, by, ax, ay) = (ax, ay, cx, cy);
+        } else {
+            (ax, ay, bx, by) = (bx, by, cx, cy);
+        }
+        (cx, cy) = (mx, my);
+    }
+    [ax, ay, bx, by, cx, cy]
+}
@@ -0,0 +1,360 @@
+use crate::analysis::{gradient, map_cells, Grid};
+use crate::errors::VulkanRError;
+use crate::renderer::WgpuRenderer;
+
+/// Apparent position of the sun in the sky of an observer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarPosition {
+    /// Degrees clockwise from north.
+    pub azimuth_deg: f64,
+    /// Degrees above the horizon, corrected for atmospheric refraction.
+    pub elevation_deg: f64,
+}
+
+impl SolarPosition {
+    /// Unit vector towards the sun in scene coordinates, where north is the
+    /// first row of the grid (-z), east the last column (+x) and up +y.
+    pub fn scene_direction(&self) -> [f32; 3] {
+        let (az, el) = (self.azimuth_deg.to_radians(), self.elevation_deg.to_radians());
+        [el.cos() * az.sin(), el.sin(), -el.cos() * az.cos()].map(|c| c as f32)
+    }
+}
+
+/// Sun position at `unix_seconds` (seconds since 1970-01-01 UTC) seen from
+/// `latitude_deg` north and `longitude_deg` east, with the NOAA solar
+/// calculator equations (Meeus' low-precision theory): within about 0.01°
+/// for dates between 1800 and 2100, outside which accuracy degrades slowly.
+pub fn solar_position(unix_seconds: f64, latitude_deg: f64, longitude_deg: f64) -> Result<SolarPosition, VulkanRError> {
+    if !unix_seconds.is_finite() {
+        return Err(VulkanRError::InvalidInput {
+            param: "time",
+            reason: "must be a finite date-time".into(),
+        });
+    }
+    if !(-90.0..=90.0).contains(&latitude_deg) {
+        return Err(VulkanRError::InvalidInput {
+            param: "latitude",
+            reason: format!("must be in [-90, 90], got {latitude_deg}"),
+        });
+    }
+    if !(-180.0..=180.0).contains(&longitude_deg) {
+        return Err(VulkanRError::InvalidInput {
+            param: "longitude",
+            reason: format!("must be in [-180, 180], got {longitude_deg}"),
+        });
+    }
+
+    // Julian centuries since J2000.0
+    let julian_day = unix_seconds / 86_400.0 + 2_440_587.5;
+    let t = (julian_day - 2_451_545.0) / 36_525.0;
+
+    // Geometric mean longitude and anomaly of the sun, orbit eccentricity
+    let mean_long = (280.466_46 + t * (36_000.769_83 + t * 0.000_303_2)).rem_euclid(360.0);
+    let mean_anom = 357.529_11 + t * (35_999.050_29 - 0.000_153_7 * t);
+    let eccentricity = 0.016_708_634 - t * (0.000_042_037 + 0.000_000_126_7 * t);
+
+    let m = mean_anom.to_radians();
+    let center = m.sin() * (1.914_602 - t * (0.004_817 + 0.000_014 * t))
+        + (2.0 * m).sin() * (0.019_993 - 0.000_101 * t)
+        + (3.0 * m).sin() * 0.000_289;
+
+    // Apparent longitude, corrected for nutation and aberration
+    let omega = (125.04 - 1_934.136 * t).to_radians();
+    let apparent_long = (mean_long + center - 0.005_69 - 0.004_78 * omega.sin()).to_radians();
+    let mean_obliquity = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.000_59 - t * 0.001_813))) / 60.0) / 60.0;
+    let obliquity = (mean_obliquity + 0.002_56 * omega.cos()).to_radians();
+    let declination = (obliquity.sin() * apparent_long.sin()).asin();
+
+    // Equation of time in minutes
+    let y = (obliquity / 2.0).tan().powi(2);
+    let l0 = mean_long.to_radians();
+    let e = eccentricity;
+    let equation_of_time = 4.0
+        * (y * (2.0 * l0).sin() - 2.0 * e * m.sin() + 4.0 * e * y * m.sin() * (2.0 * l0).cos()
+            - 0.5 * y * y * (4.0 * l0).sin()
+            - 1.25 * e * e * (2.0 * m).sin())
+        .to_degrees();
+
+    // Hour angle from the true solar time
+    let utc_minutes = (unix_seconds / 60.0).rem_euclid(1_440.0);
+    let solar_minutes = (utc_minutes + equation_of_time + 4.0 * longitude_deg).rem_euclid(1_440.0);
+    let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+
+    let lat = latitude_deg.to_radians();
+    let cos_zenith = lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos();
+    let elevation = 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+    let azimuth = 180.0
+        + hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * lat.sin() - declination.tan() * lat.cos())
+            .to_degrees();
+
+    Ok(SolarPosition {
+        azimuth_deg: azimuth.rem_euclid(360.0),
+        elevation_deg: elevation + refraction(elevation),
+    })
+}
+
+/// Atmospheric refraction in degrees for a true elevation in degrees, as
+/// approximated by NOAA.
+fn refraction(elevation: f64) -> f64 {
+    let tan_e = elevation.to_radians().tan();
+    let arcsec = if elevation > 85.0 {
+        0.0
+    } else if elevation > 5.0 {
+        58.1 / tan_e - 0.07 / tan_e.powi(3) + 0.000_086 / tan_e.powi(5)
+    } else if elevation > -0.575 {
+        1_735.0 + elevation * (-518.2 + elevation * (103.4 + elevation * (-12.79 + elevation * 0.711)))
+    } else {
+        -20.772 / tan_e
+    };
+    arcsec / 3_600.0
+}
+
+/// Mean irradiance at the top of the atmosphere in W/m².
+const SOLAR_CONSTANT: f64 = 1361.0;
+
+/// Clear-sky diffuse irradiance on open horizontal ground relative to the
+/// direct normal irradiance.
+const DIFFUSE_FRACTION: f64 = 0.1;
+
+/// Most sun positions a single insolation run evaluates.
+const MAX_SUN_SAMPLES: f64 = 1.0e6;
+
+/// Per-cell sunshine over a time range, from [`insolation`].
+#[derive(Debug, Clone)]
+pub struct Insolation {
+    /// Hours with the sun above the terrain horizon and in front of the
+    /// cell's slope.
+    pub sun_hours: Vec<f32>,
+    /// Clear-sky global radiation received by the sloped surface, in kWh/m².
+    pub radiation: Vec<f32>,
+}
+
+/// Time range, site and horizon search of an [`insolation`] run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InsolationSettings {
+    /// Start of the range in seconds since 1970-01-01 UTC.
+    pub start: f64,
+    /// End of the range in seconds since 1970-01-01 UTC.
+    pub end: f64,
+    /// The sun is sampled at the middle of every interval of this length.
+    pub step_seconds: f64,
+    /// Site of the whole grid.
+    pub latitude_deg: f64,
+    pub longitude_deg: f64,
+    /// Number of azimuths the horizon of each cell is traced along.
+    pub directions: usize,
+    /// Horizon search distance in cells.
+    pub radius: usize,
+}
+
+impl InsolationSettings {
+    fn validate(&self) -> Result<(), VulkanRError> {
+        let (start, end, step) = (self.start, self.end, self.step_seconds);
+        if !(start.is_finite() && end.is_finite() && end > start) {
+            return Err(VulkanRError::InvalidInput {
+                param: "end",
+                reason: "the time range must end after it starts".into(),
+            });
+        }
+        if !(step > 0.0 && step.is_finite()) || (end - start) / step > MAX_SUN_SAMPLES {
+            return Err(VulkanRError::InvalidInput {
+                param: "step",
+                reason: format!("must be positive and give at most {MAX_SUN_SAMPLES} samples, got {step} s"),
+            });
+        }
+        if self.directions < 4 {
+            return Err(VulkanRError::InvalidInput {
+                param: "directions",
+                reason: format!("must be at least 4, got {}", self.directions),
+            });
+        }
+        if self.radius == 0 {
+            return Err(VulkanRError::InvalidInput {
+                param: "radius",
+                reason: "must be at least 1 cell".into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The sun at the middle of every step while it is above the horizon.
+    fn sun_samples(&self) -> Result<Vec<SunSample>, VulkanRError> {
+        self.validate()?;
+        let directions = self.directions;
+        let mut suns = Vec::new();
+        let mut t0 = self.start;
+        while t0 < self.end {
+            let dt = self.step_seconds.min(self.end - t0);
+            let t = t0 + 0.5 * dt;
+            t0 += self.step_seconds;
+            let sun = solar_position(t, self.latitude_deg, self.longitude_deg)?;
+            if sun.elevation_deg <= 0.0 {
+                continue;
+            }
+            let hours = dt / 3_600.0;
+            let (az, el) = (sun.azimuth_deg.to_radians(), sun.elevation_deg.to_radians());
+            let along = sun.azimuth_deg / 360.0 * directions as f64;
+            suns.push(SunSample {
+                direction: along.floor() as usize % directions,
+                frac: along.fract() as f32,
+                tan_elevation: el.tan() as f32,
+                toward: [el.cos() * az.sin(), el.cos() * az.cos(), el.sin()].map(|c| c as f32),
+                beam: (clear_sky_dni(sun.elevation_deg, t) * hours) as f32,
+                hours: hours as f32,
+            });
+        }
+        Ok(suns)
+    }
+}
+
+/// One sampled sun position with the weight of its time step.
+pub(crate) struct SunSample {
+    /// Horizon direction below the sun's azimuth and the fraction of the
+    /// way to the next one.
+    direction: usize,
+    frac: f32,
+    tan_elevation: f32,
+    /// Unit vector towards the sun in (east, north, up).
+    pub toward: [f32; 3],
+    /// Direct normal irradiance times the step length, in Wh/m².
+    pub beam: f32,
+    pub hours: f32,
+}
+
+/// Direct sun hours and clear-sky radiation for every cell of `grid` over
+/// the time range of `settings`, with the sun sampled at the middle of every
+/// step at one site for the whole grid.
+///
+/// Terrain shadows come from the horizon of each cell, traced along
+/// `settings.directions` azimuths up to `settings.radius` cells away with
+/// samples spaced further apart with distance; a cell is in the sun when the
+/// sun is above the horizon interpolated at its azimuth. The grid's first
+/// row is north. Direct normal irradiance follows the Meinel clear-sky model
+/// with the Kasten-Young air mass; diffuse light is a fixed fraction of it,
+/// scaled by the visible sky. Missing cells are NaN and don't cast shadows.
+pub fn insolation(grid: &Grid, settings: &InsolationSettings) -> Result<Insolation, VulkanRError> {
+    let suns = settings.sun_samples()?;
+    let steps = horizon_steps(grid, settings.directions);
+    let cells = map_cells(grid.rows, grid.cols, |i, j| {
+        if !grid.at(i, j).is_finite() {
+            return [f32::NAN; 2];
+        }
+        let horizon: Vec<f32> = steps.iter().map(|&step| horizon_tan(grid, i, j, step, settings.radius)).collect();
+        let n = surface_normal(grid, i, j);
+
+        let (mut hours, mut energy) = (0.0f32, 0.0f32);
+        for sun in &suns {
+            let next = (sun.direction + 1) % settings.directions;
+            let h = horizon[sun.direction] + sun.frac * (horizon[next] - horizon[sun.direction]);
+            if sun.tan_elevation <= h {
+                continue;
+            }
+            let incidence = n[0] * sun.toward[0] + n[1] * sun.toward[1] + n[2] * sun.toward[2];
+            if incidence > 0.0 {
+                hours += sun.hours;
+                energy += sun.beam * incidence;
+            }
+        }
+        [hours, (energy + diffuse(&suns, sky_fraction(&horizon))) / 1_000.0]
+    });
+    Ok(Insolation {
+        sun_hours: cells.iter().map(|c| c[0]).collect(),
+        radiation: cells.iter().map(|c| c[1]).collect(),
+    })
+}
+
+/// [`insolation`] with the direct sun computed on the GPU of `renderer`.
+/// Cast shadows come from a sun depth map of the whole grid at every sun
+/// position rather than from the traced horizon, so they reach beyond
+/// `settings.radius`; the horizon still sets the visible sky for diffuse
+/// light. The depth maps have about four texels per cell, up to the
+/// device's largest texture.
+pub fn insolation_gpu(
+    renderer: &mut WgpuRenderer,
+    grid: &Grid,
+    settings: &InsolationSettings,
+) -> Result<Insolation, VulkanRError> {
+    let suns = settings.sun_samples()?;
+    let steps = horizon_steps(grid, settings.directions);
+    let terrain = map_cells(grid.rows, grid.cols, |i, j| {
+        if !grid.at(i, j).is_finite() {
+            return [f32::NAN; 4];
+        }
+        let horizon: Vec<f32> = steps.iter().map(|&step| horizon_tan(grid, i, j, step, settings.radius)).collect();
+        let [e, n, u] = surface_normal(grid, i, j);
+        [e, n, u, sky_fraction(&horizon)]
+    });
+    let normals: Vec<[f32; 3]> = terrain.iter().map(|&[e, n, u, _]| [e, n, u]).collect();
+    let direct = renderer.direct_sun(grid, &normals, &suns)?;
+    Ok(Insolation {
+        sun_hours: terrain.iter().zip(&direct).map(|(t, d)| if t[3].is_nan() { f32::NAN } else { d[0] }).collect(),
+        radiation: terrain.iter().zip(&direct).map(|(t, d)| (d[1] + diffuse(&suns, t[3])) / 1_000.0).collect(),
+    })
+}
+
+/// Unit steps, in cells, towards each of `directions` azimuths: one cell
+/// along the longer axis, with the distance covered in the units of the
+/// heights.
+fn horizon_steps(grid: &Grid, directions: usize) -> Vec<(f32, f32, f32)> {
+    (0..directions)
+        .map(|d| {
+            let az = std::f32::consts::TAU * d as f32 / directions as f32;
+            let (dj, di) = (az.sin() / grid.dx, -az.cos() / grid.dy);
+            let scale = dj.abs().max(di.abs());
+            let (dj, di) = (dj / scale, di / scale);
+            (di, dj, (dj * grid.dx).hypot(di * grid.dy))
+        })
+        .collect()
+}
+
+/// Unit upward normal of cell `(i, j)` in (east, north, up); rows run
+/// southwards.
+fn surface_normal(grid: &Grid, i: usize, j: usize) -> [f32; 3] {
+    let (gx, gy) = gradient(grid, i, j);
+    let len = (gx * gx + gy * gy + 1.0).sqrt();
+    [-gx / len, gy / len, 1.0 / len]
+}
+
+/// Fraction of the sky hemisphere above the horizon tangents `horizon`.
+fn sky_fraction(horizon: &[f32]) -> f32 {
+    horizon.iter().map(|&t| 1.0 - t / (1.0 + t * t).sqrt()).sum::<f32>() / horizon.len() as f32
+}
+
+/// Diffuse energy in Wh/m² over all of `suns` for a visible sky fraction
+/// of `sky`.
+fn diffuse(suns: &[SunSample], sky: f32) -> f32 {
+    DIFFUSE_FRACTION as f32 * sky * suns.iter().map(|sun| sun.beam).sum::<f32>()
+}
+
+/// Tangent of the terrain horizon seen from cell `(i, j)` along `step`
+/// (row and column increments plus distance per step), at least 0. Samples
+/// are one step apart for the first 16 steps and spread out further away.
+fn horizon_tan(grid: &Grid, i: usize, j: usize, (di, dj, dist): (f32, f32, f32), radius: usize) -> f32 {
+    let z0 = grid.at(i, j);
+    let mut max_tan = 0.0f32;
+    let mut r = 1.0f32;
+    while r <= radius as f32 {
+        let (pi, pj) = ((i as f32 + r * di).round(), (j as f32 + r * dj).round());
+        if pi < 0.0 || pj < 0.0 || pi >= grid.rows as f32 || pj >= grid.cols as f32 {
+            break;
+        }
+        let h = grid.at(pi as usize, pj as usize);
+        if h.is_finite() {
+            max_tan = max_tan.max((h - z0) / (r * dist));
+        }
+        r += (r / 16.0).floor().max(1.0);
+    }
+    max_tan
+}
+
+/// Clear-sky direct normal irradiance in W/m² for the sun at
+/// `elevation_deg` at `unix_seconds`: Meinel's attenuation through the
+/// Kasten-Young air mass, with the orbital distance correction.
+fn clear_sky_dni(elevation_deg: f64, unix_seconds: f64) -> f64 {
+    let days = unix_seconds / 86_400.0 - 10_957.5;
+    let extraterrestrial = SOLAR_CONSTANT * (1.0 + 0.033 * (std::f64::consts::TAU * days / 365.25).cos());
+    let air_mass = 1.0 / (elevation_deg.to_radians().sin() + 0.505_72 * (elevation_deg + 6.079_95).powf(-1.636_4));
+    extraterrestrial * 0.7f64.powf(air_mass.powf(0.678))
+}
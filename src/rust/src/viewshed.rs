@@ -0,0 +1,236 @@
+use crate::analysis::Grid;
+use crate::errors::VulkanRError;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Viewpoint on a grid cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Observer {
+    pub row: usize,
+    pub col: usize,
+    /// Eye height above the ground, in the units of the heights.
+    pub height: f32,
+}
+
+/// Outcome of a [`line_of_sight`] query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sight {
+    pub visible: bool,
+    /// Fractional `(row, col)` where the terrain first rises above the
+    /// sight line, when it does.
+    pub obstruction: Option<(f32, f32)>,
+}
+
+/// Whether the target `height` above cell `to` can be seen from
+/// `observer`. The sight line is checked wherever it crosses a row or
+/// column through the cell centres, against the terrain interpolated
+/// between the two nearest cells; missing heights don't block it.
+pub fn line_of_sight(grid: &Grid, observer: Observer, to: (usize, usize), height: f32) -> Result<Sight, VulkanRError> {
+    let eye = eye_height(grid, observer)?;
+    let (ti, tj) = to;
+    if ti >= grid.rows || tj >= grid.cols {
+        return Err(VulkanRError::InvalidInput {
+            param: "to",
+            reason: format!("cell ({ti}, {tj}) is outside the {}x{} grid", grid.rows, grid.cols),
+        });
+    }
+    let target = grid.at(ti, tj) + height;
+    if !target.is_finite() {
+        return Err(VulkanRError::InvalidInput {
+            param: "to",
+            reason: "target cell has a missing height".into(),
+        });
+    }
+    let obstruction = first_obstruction(grid, (observer.row, observer.col), eye, to, target);
+    Ok(Sight { visible: obstruction.is_none(), obstruction })
+}
+
+/// Number of `observers` that can see a target `target_height` above each
+/// cell within `max_distance` (horizontal units of the grid spacing).
+/// Missing cells are NaN.
+///
+/// Each observer casts rays to every cell on the edge of the box within
+/// `max_distance` and carries the horizon along them (Franklin and Ray's
+/// R2), so the work per observer grows with the cells in that box rather
+/// than with cells times distance. A cell takes its visibility from the ray
+/// passing closest to its centre, whose horizon comes from the terrain
+/// interpolated where the ray crosses the rows or columns before it, as in
+/// [`line_of_sight`]. The two only disagree on the odd cell whose target
+/// grazes the horizon.
+pub fn viewshed(grid: &Grid, observers: &[Observer], target_height: f32, max_distance: f32) -> Result<Vec<f32>, VulkanRError> {
+    if observers.is_empty() {
+        return Err(VulkanRError::InvalidInput {
+            param: "observers",
+            reason: "needs at least one observer".into(),
+        });
+    }
+    if !target_height.is_finite() {
+        return Err(VulkanRError::InvalidInput {
+            param: "target_height",
+            reason: format!("must be finite, got {target_height}"),
+        });
+    }
+    if max_distance.is_nan() || max_distance <= 0.0 {
+        return Err(VulkanRError::InvalidInput {
+            param: "max_distance",
+            reason: format!("must be positive, got {max_distance}"),
+        });
+    }
+    let eyes = observers.iter().map(|&o| eye_height(grid, o)).collect::<Result<Vec<_>, _>>()?;
+
+    // Observers split across threads, all counting into one grid
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(observers.len());
+    let per_thread = observers.len().div_ceil(threads);
+    let counts: Vec<AtomicU32> = (0..grid.rows * grid.cols).map(|_| AtomicU32::new(0)).collect();
+    std::thread::scope(|scope| {
+        for (observers, eyes) in observers.chunks(per_thread).zip(eyes.chunks(per_thread)) {
+            let counts = &counts;
+            scope.spawn(move || {
+                for (&observer, &eye) in observers.iter().zip(eyes) {
+                    for k in sweep(grid, observer, eye, target_height, max_distance) {
+                        counts[k].fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    Ok(counts
+        .into_iter()
+        .zip(grid.z)
+        .map(|(count, &z)| if z.is_finite() { count.into_inner() as f32 } else { f32::NAN })
+        .collect())
+}
+
+/// Row-major indices of the cells that `observer`, with its eye at
+/// elevation `eye`, sees within `max_distance`, from rays to every cell on
+/// the edge of the box around it.
+fn sweep(grid: &Grid, observer: Observer, eye: f32, target_height: f32, max_distance: f32) -> Vec<usize> {
+    let (oi, oj) = (observer.row as i64, observer.col as i64);
+    let reach = |spacing: f32| (max_distance / spacing).min(1e9) as i64;
+    let (i0, i1) = ((oi - reach(grid.dy)).max(0), (oi + reach(grid.dy)).min(grid.rows as i64 - 1));
+    let (j0, j1) = ((oj - reach(grid.dx)).max(0), (oj + reach(grid.dx)).min(grid.cols as i64 - 1));
+    let width = (j1 - j0 + 1) as usize;
+    let slot = |i: i64, j: i64| (i - i0) as usize * width + (j - j0) as usize;
+
+    // Per cell of the box: how far the closest ray so far passes from its
+    // centre, and whether the target is above that ray's horizon
+    let mut closest = vec![f32::INFINITY; (i1 - i0 + 1) as usize * width];
+    let mut seen = vec![false; closest.len()];
+    seen[slot(oi, oj)] = true;
+    closest[slot(oi, oj)] = 0.0;
+
+    let edge = (j0..=j1)
+        .flat_map(|j| [(i0, j), (i1, j)])
+        .chain((i0 + 1..i1).flat_map(|i| [(i, j0), (i, j1)]));
+    for (ei, ej) in edge {
+        let (di, dj) = (ei - oi, ej - oj);
+        let steps = di.abs().max(dj.abs());
+        let length = (di as f32 * grid.dy).hypot(dj as f32 * grid.dx);
+        // Steepest slope from the eye to the terrain crossed so far
+        let mut horizon = f32::NEG_INFINITY;
+        for k in 1..=steps {
+            let t = k as f32 / steps as f32;
+            let (fi, fj) = (oi as f32 + t * di as f32, oj as f32 + t * dj as f32);
+            let (i, j) = (fi.round() as i64, fj.round() as i64);
+            let distance = ((i - oi) as f32 * grid.dy).hypot((j - oj) as f32 * grid.dx);
+            let offset = (fi - i as f32).abs() + (fj - j as f32).abs();
+            if distance <= max_distance && offset < closest[slot(i, j)] {
+                closest[slot(i, j)] = offset;
+                let target = grid.at(i as usize, j as usize) + target_height;
+                seen[slot(i, j)] = (target - eye) / distance >= horizon;
+            }
+            // The ray crosses a row when it runs mostly north-south, else a
+            // column; missing heights don't raise the horizon
+            let terrain = if di.abs() >= dj.abs() {
+                lerp_cols(grid, i as usize, fj)
+            } else {
+                lerp_rows(grid, fi, j as usize)
+            };
+            if terrain.is_finite() {
+                horizon = horizon.max((terrain - eye) / (t * length));
+            }
+        }
+    }
+    (i0..=i1)
+        .flat_map(|i| (j0..=j1).map(move |j| (i, j)))
+        .filter(|&(i, j)| seen[slot(i, j)])
+        .map(|(i, j)| i as usize * grid.cols + j as usize)
+        .collect()
+}
+
+/// Eye elevation of an observer, checking that it stands on the grid.
+fn eye_height(grid: &Grid, observer: Observer) -> Result<f32, VulkanRError> {
+    let Observer { row, col, height } = observer;
+    if row >= grid.rows || col >= grid.cols {
+        return Err(VulkanRError::InvalidInput {
+            param: "observers",
+            reason: format!("cell ({row}, {col}) is outside the {}x{} grid", grid.rows, grid.cols),
+        });
+    }
+    if !height.is_finite() {
+        return Err(VulkanRError::InvalidInput {
+            param: "observer_height",
+            reason: format!("must be finite, got {height}"),
+        });
+    }
+    let eye = grid.at(row, col) + height;
+    if !eye.is_finite() {
+        return Err(VulkanRError::InvalidInput {
+            param: "observers",
+            reason: format!("observer at cell ({row}, {col}) stands on a missing height"),
+        });
+    }
+    Ok(eye)
+}
+
+/// First point, nearest `from`, where the terrain rises above the straight
+/// line from elevation `eye` over `from` to `target` over `to`.
+fn first_obstruction(grid: &Grid, from: (usize, usize), eye: f32, to: (usize, usize), target: f32) -> Option<(f32, f32)> {
+    let (ai, aj) = (from.0 as i64, from.1 as i64);
+    let (di, dj) = (to.0 as i64 - ai, to.1 as i64 - aj);
+    let (ni, nj) = (di.unsigned_abs(), dj.unsigned_abs());
+    // Crossings of the rows (k of ni) and columns (k of nj) strictly
+    // between the two cells, merged in order along the line
+    let (mut kr, mut kc) = (1, 1);
+    loop {
+        let row_t = if kr < ni { kr as f32 / ni as f32 } else { f32::INFINITY };
+        let col_t = if kc < nj { kc as f32 / nj as f32 } else { f32::INFINITY };
+        let (t, terrain, at) = if row_t.min(col_t) == f32::INFINITY {
+            return None;
+        } else if row_t <= col_t {
+            let (i, j) = ((ai + di.signum() * kr as i64) as usize, aj as f32 + row_t * dj as f32);
+            kr += 1;
+            (row_t, lerp_cols(grid, i, j), (i as f32, j))
+        } else {
+            let (i, j) = (ai as f32 + col_t * di as f32, (aj + dj.signum() * kc as i64) as usize);
+            kc += 1;
+            (col_t, lerp_rows(grid, i, j), (i, j as f32))
+        };
+        if terrain > eye + t * (target - eye) {
+            return Some(at);
+        }
+    }
+}
+
+/// Height at fractional column `j` of row `i`, from the finite neighbours.
+fn lerp_cols(grid: &Grid, i: usize, j: f32) -> f32 {
+    let (j0, j1) = (j.floor() as usize, j.ceil() as usize);
+    lerp(grid.at(i, j0), grid.at(i, j1), j - j0 as f32)
+}
+
+/// Height at fractional row `i` of column `j`, from the finite neighbours.
+fn lerp_rows(grid: &Grid, i: f32, j: usize) -> f32 {
+    let (i0, i1) = (i.floor() as usize, i.ceil() as usize);
+    lerp(grid.at(i0, j), grid.at(i1, j), i - i0 as f32)
+}
+
+/// Linear interpolation that falls back to the finite end, and NaN (never
+/// blocking) when both are missing.
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    match (a.is_finite(), b.is_finite()) {
+        (true, true) => a + t * (b - a),
+        (true, false) => a,
+        (false, true) => b,
+        (false, false) => f32::NAN,
+    }
+}
+